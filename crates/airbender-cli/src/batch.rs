@@ -0,0 +1,263 @@
+//! Running one guest over a directory of inputs in parallel.
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crate::cli::{Engine, Machine};
+use crate::console;
+use crate::input;
+use crate::program::Program;
+use crate::sim::{self, RunStatus, SimulationOutcome, SimulatorOptions};
+use crate::sim_transpiler::{self, JIT_MACHINE, TranspilerOptions};
+
+pub struct BatchOptions {
+    pub engine: Engine,
+    pub machine: Machine,
+    pub cycles: usize,
+    pub jobs: usize,
+    /// Directory for per-input console output; discarded when `None`.
+    pub console_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    pub input: PathBuf,
+    pub status: RunStatus,
+    pub cycles: Option<usize>,
+    /// Output registers x10..x17.
+    pub outputs: Option<[u32; 8]>,
+    pub message: Option<String>,
+    pub elapsed_ms: u128,
+}
+
+/// Runs every file in `inputs_dir` and returns the results in input order.
+pub fn run_batch(
+    program: &Program,
+    inputs_dir: &Path,
+    options: &BatchOptions,
+) -> Result<Vec<BatchResult>> {
+    let inputs = list_inputs(inputs_dir)?;
+    // Convert the images once up front; all workers share them.
+    program.bin_words()?;
+    if options.engine == Engine::Jit {
+        if options.machine != JIT_MACHINE {
+            bail!(
+                "--engine jit only implements {JIT_MACHINE}, got {}; use --engine sim",
+                options.machine
+            );
+        }
+        program.text_words()?;
+    }
+    if let Some(dir) = &options.console_dir {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+
+    let jobs = options.jobs.clamp(1, inputs.len().max(1));
+    tracing::info!(
+        "Running {} inputs with {jobs} jobs ({:?} engine)",
+        inputs.len(),
+        options.engine
+    );
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<BatchResult>>> =
+        Mutex::new(inputs.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                loop {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(input) = inputs.get(idx) else {
+                        break;
+                    };
+                    let result = run_one(program, input, options);
+                    results.lock().expect("batch results lock poisoned")[idx] = Some(result);
+                }
+            });
+        }
+    });
+
+    Ok(results
+        .into_inner()
+        .expect("batch results lock poisoned")
+        .into_iter()
+        .map(|result| result.expect("every input is run"))
+        .collect())
+}
+
+fn run_one(program: &Program, input: &Path, options: &BatchOptions) -> BatchResult {
+    let start = Instant::now();
+    let outcome = execute(program, input, options);
+    let elapsed_ms = start.elapsed().as_millis();
+    match outcome {
+        Ok(outcome) => BatchResult {
+            input: input.to_path_buf(),
+            status: outcome.status(),
+            cycles: Some(outcome.cycles_executed),
+            outputs: Some(outcome.registers[10..18].try_into().unwrap()),
+            message: failure_message(&outcome),
+            elapsed_ms,
+        },
+        Err(err) => BatchResult {
+            input: input.to_path_buf(),
+            status: RunStatus::Error,
+            cycles: None,
+            outputs: None,
+            message: Some(format!("{err:#}")),
+            elapsed_ms,
+        },
+    }
+}
+
+fn execute(program: &Program, input: &Path, options: &BatchOptions) -> Result<SimulationOutcome> {
+    let input_words = input::parse_input_words(input)?;
+    let console_output = match &options.console_dir {
+        // Appended rather than replacing the extension, so `a.hex` and `a.txt` don't collide.
+        Some(dir) => {
+            let mut file_name = input.file_name().unwrap_or_default().to_os_string();
+            file_name.push(".console");
+            dir.join(file_name)
+        }
+        None => PathBuf::from(console::NULL_DEVICE),
+    };
+    match options.engine {
+        Engine::Sim => {
+            let sim_options = SimulatorOptions {
+                console_output: Some(console_output),
+                machine: options.machine,
+                detect_traps: true,
+                ..Default::default()
+            };
+            sim::run_simulator(program, input_words, options.cycles, sim_options, &mut [])
+        }
+        Engine::Jit => {
+            let jit_options = TranspilerOptions {
+                console_output: Some(console_output),
+                machine: options.machine,
+                ..Default::default()
+            };
+            sim_transpiler::run_transpiler(program, input_words, options.cycles, jit_options)
+        }
+    }
+}
+
+fn failure_message(outcome: &SimulationOutcome) -> Option<String> {
+    if let Some(trap) = &outcome.trap {
+        return Some(format!("{} at {}", trap.kind, trap.backtrace[0]));
+    }
+    outcome.panic.as_ref().map(|panic| match &panic.location {
+        Some(location) => format!("panicked at {location}: {}", panic.message),
+        None => format!("panicked: {}", panic.message),
+    })
+}
+
+fn list_inputs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("failed to read input directory {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+    if paths.is_empty() {
+        bail!("input directory {} is empty", dir.display());
+    }
+    Ok(paths)
+}
+
+pub fn report(results: &[BatchResult]) {
+    tracing::info!(
+        "{:<32} {:<12} {:>14} {:>10}  Outputs (x10..x17)",
+        "Input",
+        "Status",
+        "Cycles",
+        "Time (ms)"
+    );
+    for result in results {
+        let name = result.input.file_name().map_or_else(
+            || result.input.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let cycles = result
+            .cycles
+            .map_or("-".to_string(), |cycles| cycles.to_string());
+        let outputs = result.outputs.map_or("-".to_string(), |outputs| {
+            outputs.map(|value| value.to_string()).join(" ")
+        });
+        tracing::info!(
+            "{name:<32} {:<12} {cycles:>14} {:>10}  {outputs}",
+            result.status,
+            result.elapsed_ms
+        );
+        if let Some(message) = &result.message {
+            tracing::info!("    {message}");
+        }
+    }
+
+    let ok = results
+        .iter()
+        .filter(|result| result.status.is_ok())
+        .count();
+    let total_cycles: usize = results.iter().filter_map(|result| result.cycles).sum();
+    tracing::info!(
+        "{ok} of {} inputs finished successfully, {total_cycles} cycles in total",
+        results.len()
+    );
+}
+
+pub fn write_csv(results: &[BatchResult], path: &Path) -> Result<()> {
+    let mut csv =
+        String::from("input,status,cycles,x10,x11,x12,x13,x14,x15,x16,x17,elapsed_ms,message\n");
+    for result in results {
+        let cycles = result
+            .cycles
+            .map(|cycles| cycles.to_string())
+            .unwrap_or_default();
+        let outputs = match result.outputs {
+            Some(outputs) => outputs.map(|value| value.to_string()).join(","),
+            None => ",".repeat(7),
+        };
+        csv.push_str(&format!(
+            "{},{},{cycles},{outputs},{},{}\n",
+            csv_field(&result.input.display().to_string()),
+            result.status,
+            result.elapsed_ms,
+            csv_field(result.message.as_deref().unwrap_or_default())
+        ));
+    }
+    fs::write(path, csv).with_context(|| format!("failed to write {}", path.display()))?;
+    tracing::info!("Results written to {}", path.display());
+    Ok(())
+}
+
+pub fn write_json(results: &[BatchResult], path: &Path) -> Result<()> {
+    let encoded = serde_json::to_string_pretty(results)?;
+    fs::write(path, encoded).with_context(|| format!("failed to write {}", path.display()))?;
+    tracing::info!("Results written to {}", path.display());
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_csv_fields_only_when_needed() {
+        assert_eq!(csv_field("inputs/a.hex"), "inputs/a.hex");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_field(r#"say "hi""#), r#""say ""hi""""#);
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{Context, Result, bail};
+use riscv_transpiler::common_constants::delegation_types::{
+    bigint_with_control::BIGINT_OPS_WITH_CONTROL_CSR_REGISTER,
+    blake2s_with_control::BLAKE2S_DELEGATION_CSR_REGISTER,
+    keccak_special5::KECCAK_SPECIAL5_CSR_REGISTER,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::BenchCase;
+use crate::program::Program;
+use crate::sim::{self, SimulatorOptions, Step, StepAction, StepObserver};
+use crate::{input, riscv};
+
+/// Number of function changes printed per case by `bench-compare`.
+const MAX_REPORTED_FUNCTIONS: usize = 10;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Baseline {
+    pub cases: Vec<CaseMetrics>,
+}
+
+impl Baseline {
+    pub fn read(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("failed to read baseline {}", path.display()))?;
+        serde_json::from_str(&raw).context("failed to decode baseline")
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let encoded = serde_json::to_string_pretty(self)?;
+        fs::write(path, encoded)
+            .with_context(|| format!("failed to write baseline to {}", path.display()))
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct CaseMetrics {
+    pub app_bin: PathBuf,
+    pub input: PathBuf,
+    pub total_cycles: usize,
+    /// Cycles spent in each function, excluding callees.
+    pub functions: BTreeMap<String, u64>,
+    pub delegations: BTreeMap<String, u64>,
+}
+
+pub fn record(cases: &[BenchCase], output: &Path, cycles: usize) -> Result<()> {
+    if cases.is_empty() {
+        bail!("at least one --case is required");
+    }
+    let mut baseline = Baseline { cases: Vec::new() };
+    for case in cases {
+        let metrics = measure(&case.app_bin, &case.input, cycles)?;
+        tracing::info!(
+            "{} ({}): total_cycles={}",
+            case.app_bin.display(),
+            case.input.display(),
+            metrics.total_cycles
+        );
+        baseline.cases.push(metrics);
+    }
+
+    baseline.write(output)?;
+    tracing::info!("Baseline written to {}", output.display());
+    Ok(())
+}
+
+pub fn compare(baseline_path: &Path, threshold: f64, cycles: usize) -> Result<()> {
+    let baseline = Baseline::read(baseline_path)?;
+
+    let mut regressions = 0usize;
+    for old in &baseline.cases {
+        let new = measure(&old.app_bin, &old.input, cycles)?;
+        let name = format!("{} ({})", old.app_bin.display(), old.input.display());
+
+        let (before, after) = (old.total_cycles as u64, new.total_cycles as u64);
+        let mut regressed = exceeds(before, after, threshold);
+        tracing::info!(
+            "{name}: total_cycles {before} -> {after} ({})",
+            format_change(before, after)
+        );
+
+        let delegation_names = old.delegations.keys().chain(new.delegations.keys());
+        for delegation in delegation_names.collect::<BTreeSet<_>>() {
+            let before = old.delegations.get(delegation).copied().unwrap_or(0);
+            let after = new.delegations.get(delegation).copied().unwrap_or(0);
+            if before == after {
+                continue;
+            }
+            regressed |= exceeds(before, after, threshold);
+            tracing::info!(
+                "  delegation {delegation}: {before} -> {after} ({})",
+                format_change(before, after)
+            );
+        }
+
+        // Function-level numbers are informational: inlining and renames make them noisy.
+        let mut function_changes = Vec::new();
+        let function_names = old.functions.keys().chain(new.functions.keys());
+        for function in function_names.collect::<BTreeSet<_>>() {
+            let before = old.functions.get(function).copied().unwrap_or(0);
+            let after = new.functions.get(function).copied().unwrap_or(0);
+            if percent_change(before, after).is_none_or(|change| change.abs() > threshold) {
+                function_changes.push((function, before, after));
+            }
+        }
+        function_changes
+            .sort_by_key(|(_, before, after)| std::cmp::Reverse(before.abs_diff(*after)));
+        for (function, before, after) in function_changes.iter().take(MAX_REPORTED_FUNCTIONS) {
+            tracing::info!(
+                "  {function}: {before} -> {after} ({})",
+                format_change(*before, *after)
+            );
+        }
+
+        if regressed {
+            tracing::warn!("{name}: regression exceeds {threshold}% threshold");
+            regressions += 1;
+        }
+    }
+
+    if regressions > 0 {
+        bail!(
+            "{regressions} of {} case(s) regressed beyond {threshold}%",
+            baseline.cases.len()
+        );
+    }
+    tracing::info!("No regressions beyond {threshold}%");
+    Ok(())
+}
+
+fn measure(app_bin: &Path, input_path: &Path, cycles: usize) -> Result<CaseMetrics> {
+    let input_words = input::parse_input_words(input_path)?;
+    let program = Program::load(app_bin, None)?;
+    if program.symbols.is_none() {
+        tracing::warn!(
+            "No ELF symbols for {}; per-function cycles will not be recorded",
+            app_bin.display()
+        );
+    }
+
+    let mut counter = CycleCounter::default();
+    let outcome = sim::run_simulator(
+        &program,
+        input_words,
+        cycles,
+        SimulatorOptions::default(),
+        &mut [&mut counter],
+    )?;
+    if !outcome.reached_end {
+        bail!(
+            "{} did not finish within {cycles} cycles on {}",
+            app_bin.display(),
+            input_path.display()
+        );
+    }
+
+    let mut functions = BTreeMap::new();
+    if let Some(symbols) = &program.symbols {
+        for (idx, &count) in counter.per_pc.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let name = symbols
+                .lookup((idx * 4) as u32)
+                .map_or("<unknown>", |symbol| symbol.name.as_str());
+            *functions.entry(name.to_string()).or_insert(0) += count;
+        }
+    }
+
+    Ok(CaseMetrics {
+        app_bin: app_bin.to_path_buf(),
+        input: input_path.to_path_buf(),
+        total_cycles: outcome.cycles_executed,
+        functions,
+        delegations: counter.delegations,
+    })
+}
+
+#[derive(Default)]
+struct CycleCounter {
+    /// Executed instruction count, indexed by `pc / 4`.
+    per_pc: Vec<u64>,
+    delegations: BTreeMap<String, u64>,
+}
+
+impl StepObserver for CycleCounter {
+    fn on_step(&mut self, step: &Step<'_>) -> StepAction {
+        let idx = (step.pc / 4) as usize;
+        if idx >= self.per_pc.len() {
+            self.per_pc.resize(idx + 1, 0);
+        }
+        self.per_pc[idx] += 1;
+
+        if let Some(name) = riscv::csr_access(step.instruction).and_then(delegation_name) {
+            *self.delegations.entry(name.to_string()).or_insert(0) += 1;
+        }
+        StepAction::Continue
+    }
+}
+
+fn delegation_name(csr: u32) -> Option<&'static str> {
+    match csr {
+        BLAKE2S_DELEGATION_CSR_REGISTER => Some("blake2s"),
+        BIGINT_OPS_WITH_CONTROL_CSR_REGISTER => Some("bigint"),
+        KECCAK_SPECIAL5_CSR_REGISTER => Some("keccak"),
+        _ => None,
+    }
+}
+
+/// Change from `before` to `after` in percent; `None` for a count that is new, i.e. zero in the
+/// baseline, which has no relative change.
+fn percent_change(before: u64, after: u64) -> Option<f64> {
+    match (before, after) {
+        (0, 0) => Some(0.0),
+        (0, _) => None,
+        _ => Some((after as f64 - before as f64) / before as f64 * 100.0),
+    }
+}
+
+/// Whether a count grew by more than `threshold` percent; a new count always does.
+fn exceeds(before: u64, after: u64, threshold: f64) -> bool {
+    percent_change(before, after).is_none_or(|change| change > threshold)
+}
+
+fn format_change(before: u64, after: u64) -> String {
+    match percent_change(before, after) {
+        Some(change) => format!("{change:+.2}%"),
+        None => "new".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_change_handles_a_zero_baseline() {
+        assert_eq!(percent_change(200, 250), Some(25.0));
+        assert_eq!(percent_change(200, 150), Some(-25.0));
+        assert_eq!(percent_change(0, 0), Some(0.0));
+        assert_eq!(percent_change(0, 10), None);
+        assert_eq!(format_change(0, 10), "new");
+        assert_eq!(format_change(200, 201), "+0.50%");
+    }
+
+    #[test]
+    fn threshold_only_flags_growth_beyond_it() {
+        assert!(!exceeds(1000, 1010, 1.0));
+        assert!(exceeds(1000, 1011, 1.0));
+        assert!(!exceeds(1000, 500, 1.0));
+        assert!(!exceeds(0, 0, 1.0));
+        assert!(exceeds(0, 1, 1.0));
+    }
+
+    #[test]
+    fn baseline_round_trips_through_a_file() {
+        let baseline = Baseline {
+            cases: vec![CaseMetrics {
+                app_bin: PathBuf::from("guest/app.bin"),
+                input: PathBuf::from("guest/input.hex"),
+                total_cycles: 12_345,
+                functions: BTreeMap::from([("main".to_string(), 12_000)]),
+                delegations: BTreeMap::from([("blake2s".to_string(), 3)]),
+            }],
+        };
+        let path = std::env::temp_dir().join(format!(
+            "airbender-cli-{}-baseline.json",
+            std::process::id()
+        ));
+        baseline.write(&path).unwrap();
+        let read = Baseline::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), baseline);
+    }
+}
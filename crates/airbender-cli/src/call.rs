@@ -0,0 +1,118 @@
+//! Running a single guest function in the simulator (`run --entry`).
+//!
+//! The function is entered with the requested registers and a return address that points at
+//! [`RETURN_SENTINEL`]; the run ends as soon as it returns there. The guest's startup code is
+//! skipped, so initialized data is not copied from ROM and statics start out zeroed.
+
+use anyhow::{Result, bail};
+
+use crate::cli::parse_address;
+use crate::program::Program;
+use crate::riscv::{self, REGISTER_NAMES};
+use crate::sim::SimulationOutcome;
+
+/// Return address handed to the called function; never a valid instruction address.
+pub const RETURN_SENTINEL: u32 = 0xffff_fff0;
+
+/// Entry point and initial registers of a function call.
+#[derive(Debug, Clone)]
+pub struct FunctionCall {
+    pub entry: u32,
+    pub registers: [u32; 32],
+}
+
+/// Parses `<reg>=<value>`, where the register is `x<N>` or its ABI name (`a0`, `sp`, ...).
+pub fn parse_register_value(value: &str) -> Result<(usize, u32), String> {
+    let (register, number) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected `<reg>=<value>`, got `{value}`"))?;
+    let register = register.trim();
+    let index = register
+        .strip_prefix('x')
+        .and_then(|index| index.parse::<usize>().ok())
+        .filter(|&index| index < 32)
+        .or_else(|| REGISTER_NAMES.iter().position(|name| *name == register))
+        .or_else(|| (register == "fp").then_some(8))
+        .ok_or_else(|| format!("unknown register `{register}`"))?;
+    if index == 0 {
+        return Err("x0 is hardwired to zero".to_string());
+    }
+    Ok((index, parse_address(number)?))
+}
+
+impl FunctionCall {
+    /// Resolves `entry` (an address or a function name) and sets up the registers: `ra` points at
+    /// the sentinel, `sp` and `gp` come from linker symbols unless given in `overrides`.
+    pub fn new(program: &Program, entry: &str, overrides: &[(usize, u32)]) -> Result<Self> {
+        let symbols = program.symbols.as_ref();
+        let entry = match parse_address(entry) {
+            Ok(address) => address,
+            Err(_) => match symbols.and_then(|symbols| symbols.find(entry)) {
+                Some(symbol) => symbol.address,
+                None if symbols.is_none() => {
+                    bail!("--entry {entry} needs ELF symbols; pass an address instead")
+                }
+                None => bail!("no function named `{entry}`"),
+            },
+        };
+        if !entry.is_multiple_of(4) {
+            bail!("entry point 0x{entry:08x} is not word aligned");
+        }
+
+        let mut registers = [0u32; 32];
+        registers[riscv::RA as usize] = RETURN_SENTINEL;
+        if let Some(symbols) = symbols {
+            if let Some(stack_top) = symbols.stack_top() {
+                registers[riscv::SP as usize] = stack_top;
+            }
+            if let Some(global_pointer) = symbols.label("__global_pointer$") {
+                registers[riscv::GP as usize] = global_pointer;
+            }
+        }
+        for &(index, value) in overrides {
+            registers[index] = value;
+        }
+        if registers[riscv::SP as usize] == 0 {
+            bail!("no stack symbol found in the ELF; pass the stack pointer with --reg sp=<addr>");
+        }
+        Ok(Self { entry, registers })
+    }
+}
+
+/// Logs the return registers, or that the function never returned.
+pub fn report_return(outcome: &SimulationOutcome) {
+    if !outcome.reached_end {
+        tracing::error!("Function did not return to the caller");
+        return;
+    }
+    tracing::info!(
+        "Function returned after {} cycles: a0=0x{:08x} a1=0x{:08x}",
+        outcome.cycles_executed,
+        outcome.registers[10],
+        outcome.registers[11]
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_numeric_and_abi_register_names() {
+        assert_eq!(parse_register_value("x10=5"), Ok((10, 5)));
+        assert_eq!(parse_register_value("a1=0x10"), Ok((11, 0x10)));
+        assert_eq!(parse_register_value("sp=0x1000"), Ok((2, 0x1000)));
+        assert_eq!(parse_register_value("fp=1"), Ok((8, 1)));
+        assert_eq!(parse_register_value("s0=1"), Ok((8, 1)));
+    }
+
+    #[test]
+    fn rejects_invalid_registers() {
+        assert!(parse_register_value("x0=1").is_err());
+        assert!(parse_register_value("zero=1").is_err());
+        assert!(parse_register_value("x32=1").is_err());
+        assert!(parse_register_value("q1=1").is_err());
+        assert!(parse_register_value("a0").is_err());
+        assert!(parse_register_value("a0=nope").is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Nested trace of function entries and exits with cycle timestamps (`run --call-trace`).
+//!
+//! One event per line: `<cycle> -> <function>` on entry and `<cycle> <- <function> <cycles>` on
+//! exit, indented two spaces per call depth. Calls are tracked on the same shadow call stack the
+//! trap detector uses.
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::callstack::{CallEvent, CallStack};
+use crate::elf::SymbolTable;
+use crate::sim::{Step, StepAction, StepObserver};
+
+/// What the trace keeps about each call on `call_stack`.
+struct TracedCall {
+    name: String,
+    entered_at: usize,
+    /// Whether the entry was written, i.e. the call passed the filter and depth limit.
+    written: bool,
+}
+
+pub struct CallTracer<'a> {
+    symbols: Option<&'a SymbolTable>,
+    path: PathBuf,
+    out: BufWriter<File>,
+    /// Deepest call written, counted from the outermost traced call.
+    max_depth: Option<usize>,
+    /// Only calls into functions whose name contains this, and everything they call, are written.
+    filter: Option<String>,
+    call_stack: CallStack,
+    /// One entry per call on `call_stack`.
+    traced_calls: Vec<TracedCall>,
+    /// Index in `traced_calls` of the outermost call that matched the filter.
+    filter_root: Option<usize>,
+    events: usize,
+    last_cycle: usize,
+    error: Option<io::Error>,
+}
+
+impl<'a> CallTracer<'a> {
+    pub fn create(
+        path: &Path,
+        symbols: Option<&'a SymbolTable>,
+        max_depth: Option<usize>,
+        filter: Option<String>,
+    ) -> Result<Self> {
+        if symbols.is_none() {
+            tracing::warn!("No ELF symbols: the call trace shows addresses only");
+        }
+        let file = File::create(path)
+            .with_context(|| format!("failed to create call trace {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "# cycle -> function | cycle <- function cycles")?;
+        // Without a filter every call is traced, starting at the outermost one.
+        let filter_root = filter.is_none().then_some(0);
+        Ok(Self {
+            symbols,
+            path: path.to_path_buf(),
+            out,
+            max_depth,
+            filter,
+            call_stack: CallStack::default(),
+            traced_calls: Vec::new(),
+            filter_root,
+            events: 0,
+            last_cycle: 0,
+            error: None,
+        })
+    }
+
+    fn name(&self, address: u32) -> String {
+        match self.symbols.and_then(|symbols| symbols.lookup(address)) {
+            Some(symbol) if symbol.address == address => symbol.name.clone(),
+            Some(symbol) => format!("{}+0x{:x}", symbol.name, address - symbol.address),
+            None => format!("0x{address:08x}"),
+        }
+    }
+
+    fn write_event(&mut self, line: std::fmt::Arguments<'_>) {
+        if self.error.is_none()
+            && let Err(err) = self.out.write_fmt(line)
+        {
+            self.error = Some(err);
+        }
+        self.events += 1;
+    }
+
+    fn enter(&mut self, cycle: usize, target: u32) {
+        let name = self.name(target);
+        let index = self.traced_calls.len();
+        if self.filter_root.is_none()
+            && self
+                .filter
+                .as_deref()
+                .is_some_and(|filter| name.contains(filter))
+        {
+            self.filter_root = Some(index);
+        }
+        let written = self.filter_root.is_some_and(|root| {
+            self.max_depth
+                .is_none_or(|max_depth| index - root < max_depth)
+        });
+        if written {
+            let indent = 2 * (index - self.filter_root.unwrap_or(0));
+            self.write_event(format_args!("{cycle} {:indent$}-> {name}\n", ""));
+        }
+        self.traced_calls.push(TracedCall {
+            name,
+            entered_at: cycle,
+            written,
+        });
+    }
+
+    /// Leaves every call above `depth`, innermost first.
+    fn leave(&mut self, cycle: usize, depth: usize) {
+        while self.traced_calls.len() > depth {
+            let index = self.traced_calls.len() - 1;
+            let call = self.traced_calls.pop().expect("stack is non-empty");
+            if call.written {
+                let indent = 2 * (index - self.filter_root.unwrap_or(0));
+                self.write_event(format_args!(
+                    "{cycle} {:indent$}<- {} {}\n",
+                    "",
+                    call.name,
+                    cycle - call.entered_at
+                ));
+            }
+            if self.filter.is_some() && self.filter_root == Some(index) {
+                self.filter_root = None;
+            }
+        }
+    }
+
+    /// Closes the calls still running when the run ended and flushes the trace.
+    pub fn finish(mut self) -> Result<()> {
+        self.leave(self.last_cycle, 0);
+        if let Some(err) = self.error.take() {
+            return Err(err)
+                .with_context(|| format!("failed to write call trace {}", self.path.display()));
+        }
+        self.out
+            .flush()
+            .with_context(|| format!("failed to write call trace {}", self.path.display()))?;
+        tracing::info!(
+            "Call trace with {} events written to {}",
+            self.events,
+            self.path.display()
+        );
+        Ok(())
+    }
+}
+
+impl StepObserver for CallTracer<'_> {
+    fn on_step(&mut self, step: &Step<'_>) -> StepAction {
+        self.last_cycle = step.cycle;
+        match self.call_stack.on_step(step) {
+            Some(CallEvent::Call { target }) => self.enter(step.cycle, target),
+            Some(CallEvent::Return { depth }) => self.leave(step.cycle, depth),
+            None => {}
+        }
+        StepAction::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::tests::symbol_table;
+    use crate::riscv::{self, tests::RET, tests::jal_ra};
+
+    fn symbols() -> SymbolTable {
+        symbol_table(
+            &[
+                ("main", 0x100, 0x100),
+                ("foo", 0x200, 0x100),
+                ("bar", 0x300, 0x100),
+                ("baz", 0x400, 0x100),
+            ],
+            &[],
+        )
+    }
+
+    struct Run<'a> {
+        tracer: CallTracer<'a>,
+        registers: [u32; 32],
+    }
+
+    impl Run<'_> {
+        fn step(&mut self, cycle: usize, pc: u32, instruction: u32) {
+            self.tracer.on_step(&Step {
+                cycle,
+                pc,
+                instruction,
+                registers: &self.registers,
+                memory: &[],
+            });
+        }
+
+        fn call(&mut self, cycle: usize, pc: u32, target: u32) {
+            self.step(cycle, pc, jal_ra(target.wrapping_sub(pc) as i32));
+            self.registers[riscv::RA as usize] = pc + 4;
+        }
+
+        fn ret(&mut self, cycle: usize, pc: u32, return_address: u32) {
+            self.registers[riscv::RA as usize] = return_address;
+            self.step(cycle, pc, RET);
+        }
+    }
+
+    fn trace(
+        name: &str,
+        max_depth: Option<usize>,
+        filter: Option<&str>,
+        run: impl FnOnce(&mut Run<'_>),
+    ) -> String {
+        let path =
+            std::env::temp_dir().join(format!("airbender-cli-{}-{name}.txt", std::process::id()));
+        let symbols = symbols();
+        let tracer =
+            CallTracer::create(&path, Some(&symbols), max_depth, filter.map(str::to_string))
+                .unwrap();
+        let mut state = Run {
+            tracer,
+            registers: [0; 32],
+        };
+        run(&mut state);
+        state.tracer.finish().unwrap();
+        let trace = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        trace
+            .strip_prefix("# cycle -> function | cycle <- function cycles\n")
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn writes_only_calls_under_the_filter_root() {
+        let trace = trace("call-trace-filter", None, Some("foo"), |run| {
+            run.call(0, 0x100, 0x300);
+            run.call(1, 0x300, 0x200);
+            run.call(2, 0x200, 0x400);
+            run.ret(3, 0x400, 0x204);
+            run.ret(4, 0x204, 0x304);
+            // foo has returned, so bar's next call is outside the filter again.
+            run.call(5, 0x304, 0x400);
+            run.ret(6, 0x400, 0x308);
+            run.ret(7, 0x308, 0x104);
+        });
+        assert_eq!(trace, "1 -> foo\n2   -> baz\n3   <- baz 1\n4 <- foo 3\n");
+    }
+
+    #[test]
+    fn limits_depth_and_closes_skipped_frames() {
+        let trace = trace("call-trace-depth", Some(2), None, |run| {
+            run.call(0, 0x100, 0x200);
+            run.call(1, 0x200, 0x300);
+            run.call(2, 0x300, 0x400);
+            // Returns straight to main, as after tail calls.
+            run.ret(5, 0x400, 0x104);
+            run.call(6, 0x104, 0x200);
+            run.step(9, 0x200, 0x13);
+        });
+        assert_eq!(
+            trace,
+            "0 -> foo\n1   -> bar\n5   <- bar 4\n5 <- foo 5\n6 -> foo\n9 <- foo 3\n"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Shadow call stack of the guest, rebuilt from jumps that link `ra` and the returns to them.
+
+use crate::elf::SymbolTable;
+use crate::riscv::{self, Instruction};
+use crate::sim::Step;
+
+/// How an instruction changes the call stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallEvent {
+    /// A jump that links `ra`, into the function at `target`.
+    Call { target: u32 },
+    /// A jump to a pending return address; the calls from `depth` on are left.
+    Return { depth: usize },
+}
+
+#[derive(Default)]
+pub struct CallStack {
+    /// Call site and return address of the calls in progress, outermost first.
+    calls: Vec<(u32, u32)>,
+}
+
+impl CallStack {
+    /// Continues from calls returned by [`Self::calls`].
+    pub fn from_calls(calls: Vec<(u32, u32)>) -> Self {
+        Self { calls }
+    }
+
+    /// Call site and return address of the calls in progress, outermost first.
+    pub fn calls(&self) -> &[(u32, u32)] {
+        &self.calls
+    }
+
+    /// How the instruction about to be executed changes the stack, without applying it.
+    pub fn event(&self, step: &Step<'_>) -> Option<CallEvent> {
+        let (rd, target) = match riscv::decode(step.instruction) {
+            Some(Instruction::Jal { rd, offset }) => (rd, step.pc.wrapping_add_signed(offset)),
+            Some(Instruction::Jalr { rd, rs1, offset }) => (
+                rd,
+                step.registers[rs1 as usize].wrapping_add_signed(offset) & !1,
+            ),
+            _ => return None,
+        };
+        if rd == riscv::RA {
+            return Some(CallEvent::Call { target });
+        }
+        // A return, possibly skipping frames of functions that tail-called elsewhere.
+        self.calls
+            .iter()
+            .rposition(|&(_, return_address)| return_address == target)
+            .map(|depth| CallEvent::Return { depth })
+    }
+
+    /// Applies an event returned by [`Self::event`] for the same step.
+    pub fn apply(&mut self, step: &Step<'_>, event: CallEvent) {
+        match event {
+            CallEvent::Call { .. } => self.calls.push((step.pc, step.pc.wrapping_add(4))),
+            CallEvent::Return { depth } => self.calls.truncate(depth),
+        }
+    }
+
+    /// Updates the stack with the instruction about to be executed and returns the change.
+    pub fn on_step(&mut self, step: &Step<'_>) -> Option<CallEvent> {
+        let event = self.event(step)?;
+        self.apply(step, event);
+        Some(event)
+    }
+
+    /// Start addresses of the functions on the stack at `pc`, outermost first, or raw addresses
+    /// without symbols. Callers are taken from the call sites, so tail calls show up under them.
+    pub fn functions(&self, symbols: Option<&SymbolTable>, pc: u32) -> Vec<u32> {
+        let function = |pc: u32| {
+            symbols
+                .and_then(|symbols| symbols.lookup(pc))
+                .map_or(pc, |symbol| symbol.address)
+        };
+        let mut functions: Vec<u32> = self
+            .calls
+            .iter()
+            .map(|&(call_site, _)| function(call_site))
+            .collect();
+        functions.push(function(pc));
+        functions
+    }
+}
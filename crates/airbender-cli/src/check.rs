@@ -0,0 +1,206 @@
+//! Preflight validation of guest binaries before setup computation and proving.
+
+use anyhow::{Result, bail};
+use risc_v_simulator::cycle::{
+    IMStandardIsaConfig, IMStandardIsaConfigWithUnsignedMulDiv, MachineConfig,
+};
+use risc_v_simulator::runner::CUSTOM_ENTRY_POINT;
+use riscv_transpiler::common_constants::rom::ROM_BYTE_SIZE;
+
+use crate::cli::Machine;
+use crate::program::Program;
+use crate::riscv::{self, CsrOp, Instruction, MulDivOp, NON_DETERMINISM_CSR};
+
+/// Number of rejected instructions listed before the rest are summarized.
+const MAX_REPORTED_INSTRUCTIONS: usize = 20;
+
+/// Machine-mode CSRs the simulator implements for machines with standard CSR support.
+const STANDARD_CSRS: [u32; 10] = [
+    0x180, // satp
+    0x300, // mstatus
+    0x301, // misa
+    0x304, // mie
+    0x305, // mtvec
+    0x340, // mscratch
+    0x341, // mepc
+    0x342, // mcause
+    0x343, // mtval
+    0x344, // mip
+];
+
+/// Checks ROM size, `.text` layout and that every instruction is supported by `machine`.
+pub fn check_program(program: &Program, machine: Machine) -> Result<()> {
+    let path = program.path.display();
+    if program.bin.len() > ROM_BYTE_SIZE {
+        bail!(
+            "{path}: binary is {} bytes, ROM holds {ROM_BYTE_SIZE} bytes",
+            program.bin.len()
+        );
+    }
+
+    let text = program.text()?;
+    let text_start = CUSTOM_ENTRY_POINT as usize;
+    let text_in_bin = program
+        .bin
+        .get(text_start..)
+        .map(|rest| &rest[..text.len().min(rest.len())]);
+    if text_in_bin != Some(text) {
+        bail!(
+            "{path}: .text does not match the binary at 0x{text_start:08x}; \
+             are .bin and .text from the same build?"
+        );
+    }
+
+    let mut rejected = Vec::new();
+    for (idx, chunk) in text.chunks_exact(4).enumerate() {
+        let word = u32::from_le_bytes(chunk.try_into().unwrap());
+        // All-zero words are padding (and never a valid instruction).
+        if word == 0 {
+            continue;
+        }
+        let reason = match riscv::decode(word) {
+            None => "not an RV32IM + Zicsr instruction",
+            Some(instruction) if !supports(machine, &instruction) => {
+                "not supported by the selected machine"
+            }
+            Some(_) => continue,
+        };
+        rejected.push((CUSTOM_ENTRY_POINT + 4 * idx as u32, word, reason));
+    }
+
+    if rejected.is_empty() {
+        tracing::info!(
+            "{path}: {} of {ROM_BYTE_SIZE} ROM bytes used, {} instructions valid for {machine}",
+            program.bin.len(),
+            text.len() / 4
+        );
+        return Ok(());
+    }
+
+    tracing::error!("{path}: rejected instructions:");
+    for (pc, word, reason) in rejected.iter().take(MAX_REPORTED_INSTRUCTIONS) {
+        let function = program
+            .symbols
+            .as_ref()
+            .and_then(|symbols| symbols.lookup(*pc))
+            .map(|symbol| format!(" ({}+0x{:x})", symbol.name, pc - symbol.address))
+            .unwrap_or_default();
+        tracing::error!("  0x{pc:08x}{function}: 0x{word:08x} {reason}");
+    }
+    if rejected.len() > MAX_REPORTED_INSTRUCTIONS {
+        tracing::error!(
+            "  ... and {} more",
+            rejected.len() - MAX_REPORTED_INSTRUCTIONS
+        );
+    }
+    bail!(
+        "{path}: {} instructions are not supported by {machine}",
+        rejected.len()
+    )
+}
+
+/// Returns whether `machine` can execute `instruction`.
+pub fn supports(machine: Machine, instruction: &Instruction) -> bool {
+    match machine {
+        Machine::Standard => is_supported::<IMStandardIsaConfig>(instruction),
+        Machine::UnsignedMulDiv => {
+            is_supported::<IMStandardIsaConfigWithUnsignedMulDiv>(instruction)
+        }
+    }
+}
+
+fn is_supported<C: MachineConfig>(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::MulDiv { op, .. } => match op {
+            MulDivOp::Mul | MulDivOp::Mulhu => C::SUPPORT_MUL,
+            MulDivOp::Mulh | MulDivOp::Mulhsu => C::SUPPORT_MUL && C::SUPPORT_SIGNED_MUL,
+            MulDivOp::Divu | MulDivOp::Remu => C::SUPPORT_DIV,
+            MulDivOp::Div | MulDivOp::Rem => C::SUPPORT_DIV && C::SUPPORT_SIGNED_DIV,
+        },
+        Instruction::Csr { op, csr, .. } => {
+            let known = *csr == NON_DETERMINISM_CSR
+                || C::ALLOWED_DELEGATION_CSRS.contains(csr)
+                || (C::SUPPORT_STANDARD_CSRS && STANDARD_CSRS.contains(csr));
+            known && (!C::SUPPORT_ONLY_CSRRW || *op == CsrOp::Csrrw)
+        }
+        // Traps and trap returns only exist on machines that handle exceptions.
+        Instruction::Ecall | Instruction::Ebreak | Instruction::Mret | Instruction::Wfi => {
+            C::HANDLE_EXCEPTIONS
+        }
+        // With a single hart and no caches there is nothing to order, so fences are no-ops.
+        Instruction::Fence | Instruction::FenceI => true,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::riscv::{AluOp, CsrSource};
+
+    const MACHINES: [Machine; 2] = [Machine::Standard, Machine::UnsignedMulDiv];
+
+    fn mul_div(op: MulDivOp) -> Instruction {
+        Instruction::MulDiv {
+            op,
+            rd: 10,
+            rs1: 11,
+            rs2: 12,
+        }
+    }
+
+    fn csr(op: CsrOp, csr: u32) -> Instruction {
+        Instruction::Csr {
+            op,
+            rd: 10,
+            csr,
+            src: CsrSource::Register(11),
+        }
+    }
+
+    #[test]
+    fn both_machines_run_the_base_isa_and_fences() {
+        let add = Instruction::Op {
+            op: AluOp::Add,
+            rd: 10,
+            rs1: 11,
+            rs2: 12,
+        };
+        for machine in MACHINES {
+            assert!(supports(machine, &add));
+            assert!(supports(machine, &Instruction::Fence));
+            assert!(supports(machine, &Instruction::FenceI));
+            assert!(supports(machine, &riscv::decode(0x0ff0_000f).unwrap()));
+            for op in [
+                MulDivOp::Mul,
+                MulDivOp::Mulhu,
+                MulDivOp::Divu,
+                MulDivOp::Remu,
+            ] {
+                assert!(supports(machine, &mul_div(op)), "{machine}: {op:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn only_the_standard_machine_has_signed_mul_div() {
+        for op in [
+            MulDivOp::Mulh,
+            MulDivOp::Mulhsu,
+            MulDivOp::Div,
+            MulDivOp::Rem,
+        ] {
+            assert!(supports(Machine::Standard, &mul_div(op)), "{op:?}");
+            assert!(!supports(Machine::UnsignedMulDiv, &mul_div(op)), "{op:?}");
+        }
+    }
+
+    #[test]
+    fn csr_accesses_are_limited_to_known_csrs() {
+        for machine in MACHINES {
+            assert!(supports(machine, &csr(CsrOp::Csrrw, NON_DETERMINISM_CSR)));
+            // `cycle`: neither a delegation nor a machine-mode CSR.
+            assert!(!supports(machine, &csr(CsrOp::Csrrw, 0xc00)));
+        }
+    }
+}
@@ -1,6 +1,14 @@
 use clap::{Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use std::fmt;
 use std::path::PathBuf;
 
+use crate::call::parse_register_value;
+use crate::host_oracle::{OracleCommand, parse_oracle_command};
+use crate::memory_profile::DEFAULT_STACK_HEAP_MARGIN;
+use crate::preload::{MemoryLoad, parse_memory_load};
+use crate::watch::{Watchpoint, parse_watchpoint};
+
 #[derive(Parser, Debug)]
 #[command(name = "airbender", version, about = "Airbender proving system CLI")]
 pub struct Cli {
@@ -13,12 +21,160 @@ pub enum Commands {
     /// Runs the binary with provided input via the simulator.
     Run {
         app_bin: PathBuf,
+        #[arg(short, long, required_unless_present_any = ["resume", "oracle_command", "entry"])]
+        input: Option<PathBuf>,
         #[arg(short, long)]
-        input: PathBuf,
+        cycles: Option<usize>,
+        /// Writes guest console output to this file instead of stdout.
+        #[arg(long)]
+        console_output: Option<PathBuf>,
+        /// Machine (ISA) configuration; must match the one used for proving.
+        #[arg(long, value_enum, default_value_t = Machine::default())]
+        machine: Machine,
+        /// Writes source line coverage of the run to this file.
+        #[arg(long)]
+        coverage: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = CoverageFormat::Lcov)]
+        coverage_format: CoverageFormat,
+        /// Saves the machine state right before this cycle is executed.
+        #[arg(long, requires = "snapshot_file")]
+        snapshot_at: Option<usize>,
+        #[arg(long, requires = "snapshot_at")]
+        snapshot_file: Option<PathBuf>,
+        /// Continues from a snapshot taken with `--snapshot-at` instead of starting over.
+        #[arg(long)]
+        resume: Option<PathBuf>,
+        /// Answers input reads from this helper command instead of an input file.
+        #[arg(long, value_parser = parse_oracle_command, conflicts_with = "input")]
+        oracle_command: Option<OracleCommand>,
+        /// Writes the words the guest read from the input oracle to this file.
+        #[arg(long)]
+        transcript_output: Option<PathBuf>,
+        /// What a read past the end of the input does; a helper process never runs out.
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = InputExhausted::default(),
+            conflicts_with = "oracle_command"
+        )]
+        on_input_exhausted: InputExhausted,
+        /// Copies a file into guest RAM at `addr` before the run (`addr=file`, repeatable).
+        /// Bypasses the input oracle, so the run is not provable.
+        #[arg(long, value_parser = parse_memory_load, conflicts_with = "resume")]
+        load_memory: Vec<MemoryLoad>,
+        /// Calls this function (address or symbol name) instead of starting at the entry point;
+        /// the run ends when it returns.
+        #[arg(long, conflicts_with = "resume")]
+        entry: Option<String>,
+        /// Initial register value for `--entry`, e.g. `a0=5` or `x11=0x100` (repeatable).
+        #[arg(long = "reg", value_parser = parse_register_value, requires = "entry")]
+        registers: Vec<(usize, u32)>,
+        /// Logs loads and stores touching `addr[:len][:r|w|rw]` (repeatable; 4 bytes, both by default).
+        #[arg(long = "watch", value_parser = parse_watchpoint)]
+        watchpoints: Vec<Watchpoint>,
+        /// Stops the run at the first watchpoint hit.
+        #[arg(long, requires = "watchpoints")]
+        watch_stop: bool,
+        /// Reports stack depth, heap high-water mark and the most accessed memory pages.
+        #[arg(long)]
+        memory_profile: bool,
+        /// Warns when the stack comes within this many bytes of the heap.
+        #[arg(long, requires = "memory_profile", default_value_t = DEFAULT_STACK_HEAP_MARGIN)]
+        stack_heap_margin: u32,
+        /// Writes every function entry and exit, with cycle timestamps, to this file.
+        #[arg(long)]
+        call_trace: Option<PathBuf>,
+        /// Maximum nesting depth of calls written to the call trace.
+        #[arg(long, requires = "call_trace")]
+        call_trace_depth: Option<usize>,
+        /// Traces only calls into functions whose name contains this, and their callees.
+        #[arg(long, requires = "call_trace")]
+        call_trace_filter: Option<String>,
+        /// Runs without stopping on traps, which saves an instruction decode per cycle.
+        #[arg(long)]
+        no_trap_detection: bool,
+    },
+    /// Runs the binary over every input file in a directory, in parallel.
+    RunBatch {
+        app_bin: PathBuf,
+        /// Directory with input files.
+        #[arg(long)]
+        inputs: PathBuf,
+        /// Number of inputs run at once; defaults to the number of CPUs.
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        #[arg(long, value_enum, default_value_t = Engine::Sim)]
+        engine: Engine,
+        #[arg(short, long)]
+        cycles: Option<usize>,
+        /// Writes per-input results as CSV to this file.
+        #[arg(long)]
+        csv: Option<PathBuf>,
+        /// Writes per-input results as JSON to this file.
+        #[arg(long)]
+        json: Option<PathBuf>,
+        /// Writes each input's console output to `<dir>/<input file name>.console`.
+        #[arg(long)]
+        console_dir: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = Machine::default())]
+        machine: Machine,
+    },
+    /// Runs the cases of a TOML test manifest and checks their outputs and cycle counts.
+    Test {
+        manifest: PathBuf,
+        /// Writes a JUnit XML report to this file.
+        #[arg(long)]
+        junit: Option<PathBuf>,
+    },
+    /// Runs the binary over several inputs and writes their combined source line coverage.
+    Coverage {
+        app_bin: PathBuf,
+        /// Input file; can be repeated.
+        #[arg(short, long = "input", required = true)]
+        inputs: Vec<PathBuf>,
+        #[arg(short, long, default_value = "lcov.info")]
+        output: PathBuf,
+        #[arg(long, value_enum, default_value_t = CoverageFormat::Lcov)]
+        format: CoverageFormat,
         #[arg(short, long)]
         cycles: Option<usize>,
+        #[arg(long, value_enum, default_value_t = Machine::default())]
+        machine: Machine,
+    },
+    /// Mutates a corpus of inputs and saves the ones that trap, panic or run too long.
+    Fuzz {
+        app_bin: PathBuf,
+        /// Directory with seed inputs in the `--input` hex format.
+        #[arg(long)]
+        corpus: PathBuf,
+        /// Directory the offending inputs are written to.
+        #[arg(long, default_value = "fuzz-findings")]
+        out_dir: PathBuf,
+        /// `sim` runs every input in the simulator; `jit` runs them on the JIT and only re-runs
+        /// the ones that end differently from earlier runs in the simulator.
+        #[arg(long, value_enum, default_value_t = Engine::Sim)]
+        engine: Engine,
+        /// Number of mutated inputs to run.
+        #[arg(long, default_value_t = 10_000)]
+        iterations: u64,
+        /// Cycle limit of each run.
+        #[arg(short, long)]
+        cycles: Option<usize>,
+        /// Reports inputs that take more than this many cycles.
+        #[arg(long)]
+        cycle_threshold: Option<usize>,
+        /// Seed of the mutation RNG; defaults to the current time.
+        #[arg(long)]
+        seed: Option<u64>,
+        #[arg(long, value_enum, default_value_t = Machine::default())]
+        machine: Machine,
     },
     /// Runs the binary and emits a flamegraph SVG.
+    ///
+    /// Without filters the simulator's profiler renders the graph, with inlined functions from
+    /// DWARF. `--focus`, `--exclude`, `--max-depth`, `--min-percent` and `--collapse-crate`
+    /// switch to sampling a shadow call stack named from the ELF symbol table instead, so
+    /// inlined functions no longer show up.
     Flamegraph {
         app_bin: PathBuf,
         #[arg(short, long)]
@@ -33,26 +189,94 @@ pub enum Commands {
         /// Generate inverse flamegraph.
         #[arg(long)]
         inverse: bool,
+        /// Also writes a time-ordered flame chart (Chrome trace event JSON) to this file.
+        #[arg(long)]
+        flame_chart: Option<PathBuf>,
+        /// Renders only stacks through a frame matching this regex, rooted at that frame.
+        #[arg(long)]
+        focus: Option<Regex>,
+        /// Leaves out stacks through a frame matching this regex.
+        #[arg(long)]
+        exclude: Option<Regex>,
+        /// Cuts stacks after this many frames.
+        #[arg(long, value_parser = parse_max_depth)]
+        max_depth: Option<usize>,
+        /// Leaves out frames with less than this percentage of the samples.
+        #[arg(long, value_parser = parse_percent)]
+        min_percent: Option<f64>,
+        /// Merges consecutive frames of this crate (e.g. `core`, `alloc`) into one (repeatable).
+        #[arg(long = "collapse-crate")]
+        collapse_crates: Vec<String>,
         /// Optional path to ELF symbols file.
         #[arg(long)]
         elf_path: Option<PathBuf>,
+        /// Writes guest console output to this file instead of stdout.
+        #[arg(long)]
+        console_output: Option<PathBuf>,
+        /// Machine (ISA) configuration; must match the one used for proving.
+        #[arg(long, value_enum, default_value_t = Machine::default())]
+        machine: Machine,
+        /// Logs loads and stores touching `addr[:len][:r|w|rw]` (repeatable; 4 bytes, both by default).
+        #[arg(long = "watch", value_parser = parse_watchpoint)]
+        watchpoints: Vec<Watchpoint>,
+        /// Stops the run at the first watchpoint hit.
+        #[arg(long, requires = "watchpoints")]
+        watch_stop: bool,
     },
     /// Runs the binary via the transpiler JIT.
     RunTranspiler {
         app_bin: PathBuf,
-        #[arg(short, long)]
-        input: PathBuf,
+        #[arg(short, long, required_unless_present = "oracle_command")]
+        input: Option<PathBuf>,
+        /// Answers input reads from this helper command instead of an input file.
+        #[arg(long, value_parser = parse_oracle_command, conflicts_with = "input")]
+        oracle_command: Option<OracleCommand>,
         #[arg(short, long)]
         cycles: Option<usize>,
         /// Optional path to the .text section (raw instructions).
         #[arg(long)]
         text_path: Option<PathBuf>,
+        /// Writes guest console output to this file instead of stdout.
+        #[arg(long)]
+        console_output: Option<PathBuf>,
+        /// Writes the words the guest read from the input oracle to this file.
+        #[arg(long)]
+        transcript_output: Option<PathBuf>,
+        /// What a read past the end of the input does; `trap` needs the simulator and a helper
+        /// process never runs out.
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = InputExhausted::default(),
+            conflicts_with = "oracle_command"
+        )]
+        on_input_exhausted: InputExhausted,
+        /// Copies a file into guest RAM at `addr` before the run (`addr=file`, repeatable).
+        /// Bypasses the input oracle, so the run is not provable.
+        #[arg(long, value_parser = parse_memory_load)]
+        load_memory: Vec<MemoryLoad>,
     },
     /// Generates a proof and writes it as bincode to the output file.
     Prove {
         app_bin: PathBuf,
-        #[arg(short, long)]
-        input: PathBuf,
+        #[arg(short, long, required_unless_present_any = ["oracle_transcript", "oracle_command"])]
+        input: Option<PathBuf>,
+        /// Takes the input from a transcript written by `run --transcript-output`.
+        #[arg(long, conflicts_with = "input")]
+        oracle_transcript: Option<PathBuf>,
+        /// Records the input from this helper command in a preflight run, then proves with it.
+        #[arg(
+            long,
+            value_parser = parse_oracle_command,
+            conflicts_with_all = ["input", "oracle_transcript"]
+        )]
+        oracle_command: Option<OracleCommand>,
+        /// Where the preflight run writes its transcript; defaults to `<output>.oracle.txt`.
+        #[arg(long, requires = "oracle_command")]
+        transcript_output: Option<PathBuf>,
+        /// Accepted for symmetry with `run`, but preloaded memory cannot be proven and is ignored.
+        #[arg(long, value_parser = parse_memory_load)]
+        load_memory: Vec<MemoryLoad>,
         #[arg(long)]
         output: PathBuf,
         /// Prover backend to use.
@@ -61,7 +285,7 @@ pub enum Commands {
         /// Worker thread count for the unrolled prover.
         #[arg(long, short)]
         threads: Option<usize>,
-        /// Cycle bound for CPU proving.
+        /// Cycle bound of the preflight run and of CPU proving.
         #[arg(long)]
         cycles: Option<usize>,
         /// RAM bound in bytes for CPU proving.
@@ -70,6 +294,12 @@ pub enum Commands {
         /// Max prover level to generate.
         #[arg(long, value_enum, default_value_t = ProverLevel::RecursionUnified)]
         level: ProverLevel,
+        /// Machine (ISA) configuration of the base layer.
+        #[arg(long, value_enum, default_value_t = Machine::default())]
+        machine: Machine,
+        /// Skips the JIT run before proving; `--backend cpu` then needs `--cycles`.
+        #[arg(long)]
+        no_preflight: bool,
     },
     /// Generates VKs for the requested level and writes a single bincode file.
     GenerateVk {
@@ -79,6 +309,79 @@ pub enum Commands {
         /// Max prover level to generate.
         #[arg(long, value_enum, default_value_t = ProverLevel::RecursionUnified)]
         level: ProverLevel,
+        /// Machine (ISA) configuration of the base layer.
+        #[arg(long, value_enum, default_value_t = Machine::default())]
+        machine: Machine,
+    },
+    /// Validates ROM size, `.text` layout and the instruction set of a binary.
+    Check {
+        app_bin: PathBuf,
+        /// Path to the `.text` file; defaults to the binary path with a `.text` extension.
+        #[arg(long)]
+        text_path: Option<PathBuf>,
+        /// Machine configuration the instructions are checked against.
+        #[arg(long, value_enum, default_value_t = Machine::default())]
+        machine: Machine,
+    },
+    /// Prints the `.text` section as RV32IM assembly annotated with ELF symbols.
+    Disasm {
+        app_bin: PathBuf,
+        /// ELF with symbols; defaults to the input itself or `<app>.elf` next to it.
+        #[arg(long)]
+        elf: Option<PathBuf>,
+        /// Path to the `.text` file; defaults to the binary path with a `.text` extension.
+        #[arg(long)]
+        text_path: Option<PathBuf>,
+        /// Address range (`0x100..0x200`) or function name to disassemble.
+        #[arg(long)]
+        range: Option<String>,
+        /// Machine configuration used to mark unsupported instructions.
+        #[arg(long, value_enum, default_value_t = Machine::default())]
+        machine: Machine,
+    },
+    /// Reports ROM usage per function or crate in the text and rodata sections.
+    Size {
+        app_bin: PathBuf,
+        /// ELF with symbols; defaults to the input itself or `<app>.elf` next to it.
+        #[arg(long)]
+        elf: Option<PathBuf>,
+        /// Groups symbols by crate.
+        #[arg(long)]
+        crates: bool,
+        /// Number of entries to print.
+        #[arg(short = 'n', long, default_value_t = 30)]
+        top: usize,
+        /// Writes the full report as JSON to this file.
+        #[arg(long)]
+        json: Option<PathBuf>,
+    },
+    /// Writes the padded ROM image, `.text` section and a manifest with hashes for a guest ELF.
+    Pack {
+        elf: PathBuf,
+        #[arg(long)]
+        out_dir: PathBuf,
+        /// Base name of the written files; defaults to the input file stem.
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Records cycle metrics for a set of binary/input pairs into a baseline file.
+    BenchRecord {
+        /// Binary and input pair, given as `<app.bin>:<input.hex>`. Can be repeated.
+        #[arg(long = "case", value_parser = parse_bench_case, required = true)]
+        cases: Vec<BenchCase>,
+        #[arg(short, long, default_value = "bench-baseline.json")]
+        output: PathBuf,
+        #[arg(short, long)]
+        cycles: Option<usize>,
+    },
+    /// Re-runs the cases from a baseline file and fails on cycle regressions.
+    BenchCompare {
+        baseline: PathBuf,
+        /// Allowed increase of total cycles or delegation counts, in percent.
+        #[arg(long, default_value_t = 1.0)]
+        threshold: f64,
+        #[arg(short, long)]
+        cycles: Option<usize>,
     },
     /// Verifies a proof against VKs.
     VerifyProof {
@@ -88,6 +391,9 @@ pub enum Commands {
         /// Proof level to verify.
         #[arg(long, value_enum, default_value_t = ProverLevel::RecursionUnified)]
         level: ProverLevel,
+        /// Expected machine configuration; rejects VKs generated for a different one.
+        #[arg(long, value_enum)]
+        machine: Option<Machine>,
     },
 }
 
@@ -98,8 +404,130 @@ pub enum ProverLevel {
     RecursionUnified,
 }
 
-#[derive(ValueEnum, Debug, Clone, Copy)]
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProverBackend {
     Cpu,
     Gpu,
 }
+
+/// What happens when the guest reads past the end of its input.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputExhausted {
+    /// Stop the run and report it as `input-exhausted`.
+    #[default]
+    Error,
+    /// Read zeros and keep going.
+    Zero,
+    /// Stop the run as a guest trap, with a backtrace.
+    Trap,
+}
+
+/// Execution engine: the simulator or the transpiler JIT.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    Jit,
+    Sim,
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Engine::Jit => write!(f, "jit"),
+            Engine::Sim => write!(f, "sim"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum CoverageFormat {
+    Lcov,
+    Cobertura,
+}
+
+/// Machine (ISA) configuration of the simulator and base layer circuits.
+#[derive(
+    ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Machine {
+    /// `IMStandardIsaConfig`.
+    Standard,
+    /// `IMStandardIsaConfigWithUnsignedMulDiv`, the only configuration of the GPU prover.
+    #[default]
+    UnsignedMulDiv,
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Machine::Standard => write!(f, "IMStandardIsaConfig"),
+            Machine::UnsignedMulDiv => write!(f, "IMStandardIsaConfigWithUnsignedMulDiv"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchCase {
+    pub app_bin: PathBuf,
+    pub input: PathBuf,
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal address.
+pub fn parse_address(value: &str) -> Result<u32, String> {
+    let value = value.trim();
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => value.replace('_', "").parse(),
+    };
+    parsed.map_err(|err| format!("invalid address `{value}`: {err}"))
+}
+
+fn parse_max_depth(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(0) => Err("max depth must be at least 1".to_string()),
+        Ok(depth) => Ok(depth),
+        Err(err) => Err(format!("invalid depth `{value}`: {err}")),
+    }
+}
+
+fn parse_percent(value: &str) -> Result<f64, String> {
+    let percent: f64 = value
+        .parse()
+        .map_err(|err| format!("invalid percentage `{value}`: {err}"))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("percentage must be between 0 and 100, got {value}"));
+    }
+    Ok(percent)
+}
+
+fn parse_bench_case(value: &str) -> Result<BenchCase, String> {
+    let (app_bin, input) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected `<app.bin>:<input.hex>`, got `{value}`"))?;
+    Ok(BenchCase {
+        app_bin: PathBuf::from(app_bin),
+        input: PathBuf::from(input),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_decimal_and_hex_addresses() {
+        assert_eq!(parse_address("4096"), Ok(4096));
+        assert_eq!(parse_address("0x2000_0000"), Ok(0x2000_0000));
+        assert_eq!(parse_address(" 0xff "), Ok(0xff));
+        assert_eq!(parse_address("1_000"), Ok(1000));
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        assert!(parse_address("").is_err());
+        assert!(parse_address("0x").is_err());
+        assert!(parse_address("0x1_0000_0000").is_err());
+        assert!(parse_address("main").is_err());
+        assert!(parse_address("-4").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! Guest console output.
+//!
+//! Guests print through the UART framing understood by `QuasiUARTSource`: [`UART_HELLO`], the
+//! payload length in bytes, and the payload packed little-endian into `ceil(len / 4)` words, all
+//! written to the non-determinism CSR.
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::oracle::FrameStep;
+
+/// First word of a UART frame.
+pub const UART_HELLO: u32 = u32::MAX;
+
+/// Longest payload accepted in one frame; a longer length is treated as garbage.
+pub const MAX_CONSOLE_FRAME: usize = 1 << 16;
+
+/// Amount of trailing console output kept around to decode panic messages.
+pub const PANIC_SCAN_WINDOW: usize = 4096;
+
+/// Path that discards console output, for runs whose output nobody reads.
+#[cfg(windows)]
+pub const NULL_DEVICE: &str = "NUL";
+#[cfg(not(windows))]
+pub const NULL_DEVICE: &str = "/dev/null";
+
+const PANIC_PREFIX: &str = "panicked at ";
+
+#[derive(Debug, Clone)]
+pub struct GuestPanic {
+    pub location: Option<String>,
+    pub message: String,
+}
+
+pub fn open_sink(path: Option<&Path>) -> Result<Box<dyn Write + Send>> {
+    match path {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("failed to create console output {}", path.display()))?;
+            Ok(Box::new(file))
+        }
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+/// Decodes the words following [`UART_HELLO`].
+#[derive(Debug, Default)]
+pub struct ConsoleDecoder {
+    len: Option<usize>,
+    payload: Vec<u8>,
+}
+
+impl ConsoleDecoder {
+    /// Consumes the next word of a console frame; yields the payload once the frame is complete.
+    pub fn push(&mut self, word: u32) -> FrameStep<Vec<u8>> {
+        let Some(len) = self.len else {
+            let len = word as usize;
+            if len > MAX_CONSOLE_FRAME {
+                return FrameStep::Invalid;
+            }
+            self.len = Some(len);
+            return if len == 0 {
+                FrameStep::Complete(Vec::new())
+            } else {
+                FrameStep::Pending
+            };
+        };
+
+        let remaining = len - self.payload.len();
+        self.payload
+            .extend_from_slice(&word.to_le_bytes()[..remaining.min(4)]);
+        if self.payload.len() < len {
+            return FrameStep::Pending;
+        }
+        FrameStep::Complete(std::mem::take(&mut self.payload))
+    }
+}
+
+/// Extracts the last Rust panic message (`panicked at <location>:\n<message>`) from console output.
+pub fn find_panic(output: &str) -> Option<GuestPanic> {
+    let start = output.rfind(PANIC_PREFIX)?;
+    let report = output[start + PANIC_PREFIX.len()..].trim_end();
+    match report.split_once(":\n") {
+        Some((location, message)) => Some(GuestPanic {
+            location: Some(location.to_string()),
+            message: message.to_string(),
+        }),
+        None => Some(GuestPanic {
+            location: None,
+            message: report.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(words: &[u32]) -> Vec<FrameStep<Vec<u8>>> {
+        let mut decoder = ConsoleDecoder::default();
+        words.iter().map(|&word| decoder.push(word)).collect()
+    }
+
+    #[test]
+    fn decodes_a_frame_with_a_partial_last_word() {
+        let steps = decode(&[
+            6,
+            u32::from_le_bytes(*b"hell"),
+            u32::from_le_bytes(*b"o!\0\0"),
+        ]);
+        assert_eq!(
+            steps,
+            [
+                FrameStep::Pending,
+                FrameStep::Pending,
+                FrameStep::Complete(b"hello!".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn completes_an_empty_frame_at_the_length() {
+        assert_eq!(decode(&[0]), [FrameStep::Complete(Vec::new())]);
+    }
+
+    #[test]
+    fn rejects_lengths_above_the_cap() {
+        assert_eq!(decode(&[MAX_CONSOLE_FRAME as u32]), [FrameStep::Pending]);
+        assert_eq!(
+            decode(&[MAX_CONSOLE_FRAME as u32 + 1]),
+            [FrameStep::Invalid]
+        );
+        assert_eq!(decode(&[UART_HELLO]), [FrameStep::Invalid]);
+    }
+
+    #[test]
+    fn finds_the_last_panic() {
+        let output = "panicked at src/a.rs:1:1:\nfirst\npanicked at src/main.rs:7:5:\nboom\n";
+        let panic = find_panic(output).unwrap();
+        assert_eq!(panic.location.as_deref(), Some("src/main.rs:7:5"));
+        assert_eq!(panic.message, "boom");
+        assert!(find_panic("all good\n").is_none());
+    }
+}
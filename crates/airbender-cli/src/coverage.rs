@@ -0,0 +1,254 @@
+//! Source line coverage from simulator runs, written as lcov or Cobertura XML.
+
+use anyhow::{Context, Result, anyhow, bail};
+use risc_v_simulator::runner::CUSTOM_ENTRY_POINT;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::cli::CoverageFormat;
+use crate::program::Program;
+use crate::sim::{Step, StepAction, StepObserver};
+use crate::xml;
+
+/// Counts how often every instruction was executed.
+#[derive(Debug, Default)]
+pub struct CoverageRecorder {
+    /// Execution count, indexed by `pc / 4`.
+    hits: Vec<u64>,
+}
+
+impl CoverageRecorder {
+    /// Adds the counts of another run, e.g. one with a different input.
+    pub fn merge(&mut self, other: &CoverageRecorder) {
+        if self.hits.len() < other.hits.len() {
+            self.hits.resize(other.hits.len(), 0);
+        }
+        for (total, count) in self.hits.iter_mut().zip(&other.hits) {
+            *total += count;
+        }
+    }
+
+    fn hits_at(&self, pc: u64) -> u64 {
+        self.hits.get((pc / 4) as usize).copied().unwrap_or(0)
+    }
+}
+
+impl StepObserver for CoverageRecorder {
+    fn on_step(&mut self, step: &Step<'_>) -> StepAction {
+        let idx = (step.pc / 4) as usize;
+        if idx >= self.hits.len() {
+            self.hits.resize(idx + 1, 0);
+        }
+        self.hits[idx] += 1;
+        StepAction::Continue
+    }
+}
+
+/// Fails early when `program` has no ELF to read DWARF line info from.
+pub fn require_debug_info(program: &Program) -> Result<()> {
+    if program.elf_path.is_none() {
+        bail!(
+            "coverage needs an ELF with debug info for {}",
+            program.path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Maps executed instructions to source lines and writes the report to `output`.
+pub fn write_report(
+    program: &Program,
+    recorder: &CoverageRecorder,
+    output: &Path,
+    format: CoverageFormat,
+) -> Result<()> {
+    require_debug_info(program)?;
+    let elf_path = program.elf_path.as_ref().unwrap();
+    let loader = addr2line::Loader::new(elf_path)
+        .map_err(|err| anyhow!("failed to load DWARF from {}: {err}", elf_path.display()))?;
+
+    // Every instruction with line info makes its line instrumentable; a line counts as executed
+    // as often as its most executed instruction.
+    let mut files: BTreeMap<String, BTreeMap<u32, u64>> = BTreeMap::new();
+    let text_start = u64::from(CUSTOM_ENTRY_POINT);
+    let text_end = text_start + program.text()?.len() as u64;
+    let locations = loader
+        .find_location_range(text_start, text_end)
+        .map_err(|err| anyhow!("failed to read DWARF line info: {err}"))?;
+    for (start, len, location) in locations {
+        let (Some(file), Some(line)) = (location.file, location.line) else {
+            continue;
+        };
+        let hits = (start..start + len)
+            .step_by(4)
+            .map(|pc| recorder.hits_at(pc))
+            .max()
+            .unwrap_or(0);
+        let entry = files
+            .entry(file.to_string())
+            .or_default()
+            .entry(line)
+            .or_default();
+        *entry = (*entry).max(hits);
+    }
+    if files.is_empty() {
+        bail!(
+            "{} has no DWARF line info; build the guest with debug = true",
+            elf_path.display()
+        );
+    }
+
+    let report = match format {
+        CoverageFormat::Lcov => lcov(&files),
+        CoverageFormat::Cobertura => cobertura(&files),
+    };
+    fs::write(output, report)
+        .with_context(|| format!("failed to write coverage to {}", output.display()))?;
+
+    let lines_valid: usize = files.values().map(BTreeMap::len).sum();
+    let lines_covered = count_covered(&files);
+    tracing::info!(
+        "Coverage: {lines_covered} of {lines_valid} lines ({:.2}%) in {} files, written to {}",
+        rate(lines_covered, lines_valid) * 100.0,
+        files.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn lcov(files: &BTreeMap<String, BTreeMap<u32, u64>>) -> String {
+    let mut out = String::new();
+    for (file, lines) in files {
+        writeln!(out, "SF:{file}").unwrap();
+        for (line, hits) in lines {
+            writeln!(out, "DA:{line},{hits}").unwrap();
+        }
+        writeln!(out, "LF:{}", lines.len()).unwrap();
+        writeln!(
+            out,
+            "LH:{}",
+            lines.values().filter(|&&hits| hits > 0).count()
+        )
+        .unwrap();
+        writeln!(out, "end_of_record").unwrap();
+    }
+    out
+}
+
+fn cobertura(files: &BTreeMap<String, BTreeMap<u32, u64>>) -> String {
+    let lines_valid: usize = files.values().map(BTreeMap::len).sum();
+    let lines_covered = count_covered(files);
+    let line_rate = rate(lines_covered, lines_valid);
+
+    let mut out = String::new();
+    writeln!(out, r#"<?xml version="1.0" ?>"#).unwrap();
+    writeln!(
+        out,
+        r#"<coverage line-rate="{line_rate:.4}" branch-rate="0" lines-covered="{lines_covered}" lines-valid="{lines_valid}" branches-covered="0" branches-valid="0" complexity="0" version="airbender-cli" timestamp="0">"#
+    )
+    .unwrap();
+    writeln!(out, "  <sources><source>.</source></sources>").unwrap();
+    writeln!(out, "  <packages>").unwrap();
+    writeln!(
+        out,
+        r#"    <package name="guest" line-rate="{line_rate:.4}" branch-rate="0" complexity="0">"#
+    )
+    .unwrap();
+    writeln!(out, "      <classes>").unwrap();
+    for (file, lines) in files {
+        let covered = lines.values().filter(|&&hits| hits > 0).count();
+        let file = xml::escape(file);
+        writeln!(
+            out,
+            r#"        <class name="{file}" filename="{file}" line-rate="{:.4}" branch-rate="0" complexity="0">"#,
+            rate(covered, lines.len())
+        )
+        .unwrap();
+        writeln!(out, "          <methods/>").unwrap();
+        writeln!(out, "          <lines>").unwrap();
+        for (line, hits) in lines {
+            writeln!(out, r#"            <line number="{line}" hits="{hits}"/>"#).unwrap();
+        }
+        writeln!(out, "          </lines>").unwrap();
+        writeln!(out, "        </class>").unwrap();
+    }
+    writeln!(out, "      </classes>").unwrap();
+    writeln!(out, "    </package>").unwrap();
+    writeln!(out, "  </packages>").unwrap();
+    writeln!(out, "</coverage>").unwrap();
+    out
+}
+
+fn count_covered(files: &BTreeMap<String, BTreeMap<u32, u64>>) -> usize {
+    files
+        .values()
+        .flat_map(BTreeMap::values)
+        .filter(|&&hits| hits > 0)
+        .count()
+}
+
+fn rate(covered: usize, valid: usize) -> f64 {
+    if valid == 0 {
+        0.0
+    } else {
+        covered as f64 / valid as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> BTreeMap<String, BTreeMap<u32, u64>> {
+        BTreeMap::from([
+            (
+                "src/main.rs".to_string(),
+                BTreeMap::from([(3, 1), (4, 0), (7, 12)]),
+            ),
+            ("src/<lib>.rs".to_string(), BTreeMap::from([(1, 0)])),
+        ])
+    }
+
+    #[test]
+    fn writes_lcov_records_per_file() {
+        assert_eq!(
+            lcov(&files()),
+            "SF:src/<lib>.rs\nDA:1,0\nLF:1\nLH:0\nend_of_record\n\
+             SF:src/main.rs\nDA:3,1\nDA:4,0\nDA:7,12\nLF:3\nLH:2\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn writes_cobertura_rates_and_escapes_file_names() {
+        let report = cobertura(&files());
+        assert!(
+            report.contains(
+                r#"line-rate="0.5000" branch-rate="0" lines-covered="2" lines-valid="4""#
+            )
+        );
+        assert!(report.contains(
+            r#"<class name="src/&lt;lib&gt;.rs" filename="src/&lt;lib&gt;.rs" line-rate="0.0000""#
+        ));
+        assert!(
+            report
+                .contains(r#"<class name="src/main.rs" filename="src/main.rs" line-rate="0.6667""#)
+        );
+        assert!(report.contains(r#"<line number="7" hits="12"/>"#));
+        assert!(report.trim_end().ends_with("</coverage>"));
+    }
+
+    #[test]
+    fn merges_hit_counts() {
+        let mut total = CoverageRecorder {
+            hits: vec![1, 0, 2],
+        };
+        total.merge(&CoverageRecorder {
+            hits: vec![0, 3, 1, 4],
+        });
+        assert_eq!(total.hits, [1, 3, 3, 4]);
+        assert_eq!(total.hits_at(12), 4);
+        assert_eq!(total.hits_at(400), 0);
+    }
+}
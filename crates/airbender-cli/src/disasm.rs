@@ -0,0 +1,103 @@
+//! Symbol-annotated disassembly of the `.text` section.
+
+use anyhow::{Context, Result, bail};
+use risc_v_simulator::runner::CUSTOM_ENTRY_POINT;
+use std::collections::BTreeSet;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+
+use crate::check;
+use crate::cli::{self, Machine};
+use crate::elf::SymbolTable;
+use crate::program::Program;
+use crate::riscv;
+
+/// Prints the `.text` section (or the `range` part of it) as assembly to stdout.
+///
+/// `range` is either `<start>..<end>` or the name of a function.
+pub fn disassemble(program: &Program, machine: Machine, range: Option<&str>) -> Result<()> {
+    let text = program.text()?;
+    let text_end = CUSTOM_ENTRY_POINT + text.len() as u32;
+    let range = match range {
+        Some(range) => resolve_range(range, program.symbols.as_ref())?,
+        None => CUSTOM_ENTRY_POINT..text_end,
+    };
+    if program.symbols.is_none() {
+        tracing::warn!("No ELF symbols for {}: pass --elf", program.path.display());
+    }
+
+    let instructions: Vec<(u32, u32)> = text
+        .chunks_exact(4)
+        .enumerate()
+        .map(|(idx, chunk)| {
+            let pc = CUSTOM_ENTRY_POINT + 4 * idx as u32;
+            (pc, u32::from_le_bytes(chunk.try_into().unwrap()))
+        })
+        .filter(|(pc, _)| range.contains(pc))
+        .collect();
+    let branch_targets: BTreeSet<u32> = instructions
+        .iter()
+        .filter_map(|&(pc, word)| riscv::decode(word)?.jump_target(pc))
+        .collect();
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut unsupported = 0usize;
+    for &(pc, word) in &instructions {
+        if let Some(symbols) = &program.symbols
+            && let Some(symbol) = symbols.lookup(pc)
+        {
+            if symbol.address == pc {
+                writeln!(out, "\n{pc:08x} <{}>:", symbol.name)?;
+            } else if branch_targets.contains(&pc) {
+                writeln!(out, "  <{}+0x{:x}>:", symbol.name, pc - symbol.address)?;
+            }
+        }
+
+        let line = match riscv::decode(word) {
+            Some(instruction) => {
+                let mut line = instruction.assembly(pc);
+                if let Some(target) = instruction.jump_target(pc) {
+                    line.push_str(&describe_address(target, program.symbols.as_ref()));
+                }
+                if !check::supports(machine, &instruction) {
+                    unsupported += 1;
+                    line.push_str(&format!("  # not supported by {machine}"));
+                }
+                line
+            }
+            None => format!(".word 0x{word:08x}"),
+        };
+        writeln!(out, "{pc:8x}:\t{word:08x}\t{line}")?;
+    }
+    out.flush()?;
+
+    if unsupported > 0 {
+        tracing::warn!("{unsupported} instructions are not supported by {machine}");
+    }
+    Ok(())
+}
+
+fn describe_address(address: u32, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|symbols| symbols.lookup(address)) {
+        Some(symbol) if symbol.address == address => format!(" <{}>", symbol.name),
+        Some(symbol) => format!(" <{}+0x{:x}>", symbol.name, address - symbol.address),
+        None => String::new(),
+    }
+}
+
+fn resolve_range(range: &str, symbols: Option<&SymbolTable>) -> Result<Range<u32>> {
+    if let Some((start, end)) = range.split_once("..") {
+        let start = cli::parse_address(start).map_err(anyhow::Error::msg)?;
+        let end = cli::parse_address(end).map_err(anyhow::Error::msg)?;
+        if start >= end {
+            bail!("empty range 0x{start:x}..0x{end:x}");
+        }
+        return Ok(start..end);
+    }
+
+    let symbols = symbols.context("resolving a function name requires ELF symbols")?;
+    let symbol = symbols
+        .find(range)
+        .with_context(|| format!("no function named `{range}`"))?;
+    Ok(symbols.extent(symbol))
+}
@@ -0,0 +1,365 @@
+use anyhow::{Context, Result, bail};
+use object::elf::{EM_RISCV, PT_LOAD};
+use object::read::elf::{ElfFile32, FileHeader, ProgramHeader};
+use object::{LittleEndian, Object, ObjectSection, ObjectSymbol, SymbolKind};
+use risc_v_simulator::runner::CUSTOM_ENTRY_POINT;
+use riscv_transpiler::common_constants::rom::ROM_BYTE_SIZE;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+pub const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+
+/// Linker symbols tried, in order, for the top of the stack.
+const STACK_TOP_SYMBOLS: [&str; 4] = ["_stack_start", "_stack_top", "_estack", "__stack_top"];
+
+/// Linker symbols tried, in order, for the start of the heap.
+const HEAP_START_SYMBOLS: [&str; 4] = ["_heap_start", "_sheap", "__heap_start", "_end"];
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+}
+
+/// Function symbols of a guest ELF, sorted by address.
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    functions: Vec<Symbol>,
+    /// Addresses of all other named symbols, e.g. linker-defined stack and heap bounds.
+    labels: HashMap<String, u32>,
+}
+
+impl SymbolTable {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let file = object::File::parse(&*data)
+            .with_context(|| format!("failed to parse ELF {}", path.display()))?;
+        Ok(Self::from_object(&file))
+    }
+
+    pub fn from_object(file: &object::File<'_>) -> Self {
+        let mut functions = Vec::new();
+        let mut labels = HashMap::new();
+        for symbol in file.symbols() {
+            let Ok(name) = symbol.name() else {
+                continue;
+            };
+            if name.is_empty() {
+                continue;
+            }
+            if symbol.kind() != SymbolKind::Text {
+                labels
+                    .entry(format!("{:#}", rustc_demangle::demangle(name)))
+                    .or_insert(symbol.address() as u32);
+                continue;
+            }
+            functions.push(Symbol {
+                name: format!("{:#}", rustc_demangle::demangle(name)),
+                address: symbol.address() as u32,
+                size: symbol.size() as u32,
+            });
+        }
+        functions.sort_by_key(|symbol| symbol.address);
+        functions.dedup_by_key(|symbol| symbol.address);
+
+        Self { functions, labels }
+    }
+
+    /// Returns the address of a non-function symbol, such as a linker script label.
+    pub fn label(&self, name: &str) -> Option<u32> {
+        self.labels.get(name).copied()
+    }
+
+    /// Initial stack pointer, from the linker script.
+    pub fn stack_top(&self) -> Option<u32> {
+        STACK_TOP_SYMBOLS.iter().find_map(|name| self.label(name))
+    }
+
+    /// First address of the heap, from the linker script.
+    pub fn heap_start(&self) -> Option<u32> {
+        HEAP_START_SYMBOLS.iter().find_map(|name| self.label(name))
+    }
+
+    /// Returns the function with the given demangled name.
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.functions.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// Returns the addresses covered by `symbol`, matching what [`SymbolTable::lookup`] resolves.
+    pub fn extent(&self, symbol: &Symbol) -> Range<u32> {
+        let end = match symbol.size {
+            0 => {
+                let next = self
+                    .functions
+                    .partition_point(|other| other.address <= symbol.address);
+                self.functions
+                    .get(next)
+                    .map_or(u32::MAX, |other| other.address)
+            }
+            size => symbol.address.saturating_add(size),
+        };
+        symbol.address..end
+    }
+
+    /// Returns the function containing `pc`, if any.
+    pub fn lookup(&self, pc: u32) -> Option<&Symbol> {
+        let idx = self
+            .functions
+            .partition_point(|symbol| symbol.address <= pc)
+            .checked_sub(1)?;
+        let symbol = &self.functions[idx];
+        // Symbols without a size (e.g. hand-written assembly labels) extend to the next symbol.
+        if symbol.size == 0 || pc < symbol.address.saturating_add(symbol.size) {
+            Some(symbol)
+        } else {
+            None
+        }
+    }
+}
+
+/// ROM contents extracted from a guest ELF.
+#[derive(Debug)]
+pub struct RomImage {
+    /// Loadable segments laid out by load address from address 0, as `objcopy -O binary` does.
+    pub bin: Vec<u8>,
+    /// Contents of the `.text` section.
+    pub text: Vec<u8>,
+}
+
+/// Extracts the ROM image and checks that the ELF is linked for the Airbender machine.
+pub fn extract_rom_image(data: &[u8]) -> Result<RomImage> {
+    let elf = ElfFile32::<LittleEndian>::parse(data).context("not a 32-bit little-endian ELF")?;
+    let endian = elf.endian();
+    let header = elf.elf_header();
+    if header.e_machine(endian) != EM_RISCV {
+        bail!("ELF is not a RISC-V binary");
+    }
+    let entry = header.e_entry(endian);
+    if entry != CUSTOM_ENTRY_POINT {
+        bail!("ELF entry point is 0x{entry:08x}, expected 0x{CUSTOM_ENTRY_POINT:08x}");
+    }
+
+    let text = elf
+        .section_by_name(".text")
+        .context("ELF has no .text section")?;
+    if text.address() != u64::from(CUSTOM_ENTRY_POINT) {
+        bail!(
+            ".text starts at 0x{:08x}, expected 0x{CUSTOM_ENTRY_POINT:08x}",
+            text.address()
+        );
+    }
+    let text = text
+        .data()
+        .context("failed to read .text section")?
+        .to_vec();
+
+    let mut bin = Vec::new();
+    for segment in elf.elf_program_headers() {
+        let file_size = segment.p_filesz(endian) as usize;
+        if segment.p_type(endian) != PT_LOAD || file_size == 0 {
+            continue;
+        }
+        // Initialized data is stored in ROM at its load address and copied to RAM by the guest.
+        let start = segment.p_paddr(endian) as usize;
+        let end = start + file_size;
+        if end > ROM_BYTE_SIZE {
+            bail!(
+                "loadable segment 0x{start:08x}..0x{end:08x} does not fit into ROM ({ROM_BYTE_SIZE} bytes)"
+            );
+        }
+        let contents = segment
+            .data(endian, data)
+            .map_err(|()| anyhow::anyhow!("segment at 0x{start:08x} is truncated"))?;
+        if bin.len() < end {
+            bin.resize(end, 0);
+        }
+        bin[start..end].copy_from_slice(contents);
+    }
+    bin.resize(bin.len().next_multiple_of(4), 0);
+
+    let text_start = CUSTOM_ENTRY_POINT as usize;
+    if bin.get(text_start..text_start + text.len()) != Some(text.as_slice()) {
+        bail!(".text section does not match the ROM image at the entry point");
+    }
+    Ok(RomImage { bin, text })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use object::elf::{EM_386, PF_R, PF_X, SHF_ALLOC, SHF_EXECINSTR, SHT_PROGBITS, SHT_STRTAB};
+
+    /// Layout of a hand-built guest ELF: one loadable segment and a `.text` section.
+    pub(crate) struct TestElf {
+        pub machine: u16,
+        pub entry: u32,
+        pub segment_address: u32,
+        pub segment: Vec<u8>,
+        pub text_address: u32,
+        pub text: Vec<u8>,
+    }
+
+    impl TestElf {
+        /// A valid guest whose only segment is its `.text`.
+        pub(crate) fn guest(text: &[u8]) -> Self {
+            Self {
+                machine: EM_RISCV,
+                entry: CUSTOM_ENTRY_POINT,
+                segment_address: CUSTOM_ENTRY_POINT,
+                segment: text.to_vec(),
+                text_address: CUSTOM_ENTRY_POINT,
+                text: text.to_vec(),
+            }
+        }
+
+        pub(crate) fn build(&self) -> Vec<u8> {
+            const HEADER_SIZE: usize = 52;
+            const PROGRAM_HEADER_SIZE: usize = 32;
+            const SECTION_HEADER_SIZE: usize = 40;
+            let names = b"\0.text\0.shstrtab\0";
+            let segment_offset = HEADER_SIZE + PROGRAM_HEADER_SIZE;
+            let text_offset = segment_offset + self.segment.len();
+            let names_offset = text_offset + self.text.len();
+            let sections_offset = (names_offset + names.len()).next_multiple_of(4);
+
+            let mut out = Vec::new();
+            let half = |out: &mut Vec<u8>, value: u16| out.extend_from_slice(&value.to_le_bytes());
+            let word = |out: &mut Vec<u8>, value: u32| out.extend_from_slice(&value.to_le_bytes());
+            out.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+            out.resize(16, 0);
+            half(&mut out, 2);
+            half(&mut out, self.machine);
+            word(&mut out, 1);
+            word(&mut out, self.entry);
+            word(&mut out, HEADER_SIZE as u32);
+            word(&mut out, sections_offset as u32);
+            word(&mut out, 0);
+            half(&mut out, HEADER_SIZE as u16);
+            half(&mut out, PROGRAM_HEADER_SIZE as u16);
+            half(&mut out, 1);
+            half(&mut out, SECTION_HEADER_SIZE as u16);
+            half(&mut out, 3);
+            half(&mut out, 2);
+
+            for value in [
+                PT_LOAD,
+                segment_offset as u32,
+                self.segment_address,
+                self.segment_address,
+                self.segment.len() as u32,
+                self.segment.len() as u32,
+                PF_R | PF_X,
+                4,
+            ] {
+                word(&mut out, value);
+            }
+            out.extend_from_slice(&self.segment);
+            out.extend_from_slice(&self.text);
+            out.extend_from_slice(names);
+            out.resize(sections_offset, 0);
+
+            out.resize(out.len() + SECTION_HEADER_SIZE, 0);
+            let text_section = [
+                1,
+                SHT_PROGBITS,
+                SHF_ALLOC | SHF_EXECINSTR,
+                self.text_address,
+                text_offset as u32,
+                self.text.len() as u32,
+                0,
+                0,
+                4,
+                0,
+            ];
+            let names_section = [
+                7,
+                SHT_STRTAB,
+                0,
+                0,
+                names_offset as u32,
+                names.len() as u32,
+                0,
+                0,
+                1,
+                0,
+            ];
+            for value in text_section.into_iter().chain(names_section) {
+                word(&mut out, value);
+            }
+            out
+        }
+    }
+
+    const TEXT: [u8; 8] = [0x13, 0x05, 0x15, 0x00, 0x6f, 0xf0, 0xdf, 0xff];
+
+    fn error(elf: TestElf) -> String {
+        format!("{:#}", extract_rom_image(&elf.build()).unwrap_err())
+    }
+
+    /// Symbol table of `(name, address, size)` functions and `(name, address)` labels.
+    pub(crate) fn symbol_table(
+        functions: &[(&str, u32, u32)],
+        labels: &[(&str, u32)],
+    ) -> SymbolTable {
+        SymbolTable {
+            functions: functions
+                .iter()
+                .map(|&(name, address, size)| Symbol {
+                    name: name.to_string(),
+                    address,
+                    size,
+                })
+                .collect(),
+            labels: labels
+                .iter()
+                .map(|&(name, address)| (name.to_string(), address))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn extracts_segments_and_text() {
+        let mut elf = TestElf::guest(&TEXT);
+        // Initialized data right after the code, with a length that needs padding.
+        elf.segment.extend_from_slice(&[1, 2]);
+        let image = extract_rom_image(&elf.build()).unwrap();
+        assert_eq!(image.text, TEXT);
+        assert_eq!(image.bin[..TEXT.len()], TEXT);
+        assert_eq!(image.bin[TEXT.len()..], [1, 2, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_other_machines() {
+        let mut elf = TestElf::guest(&TEXT);
+        elf.machine = EM_386;
+        assert!(error(elf).contains("not a RISC-V binary"));
+    }
+
+    #[test]
+    fn rejects_a_different_entry_point() {
+        let mut elf = TestElf::guest(&TEXT);
+        elf.entry = CUSTOM_ENTRY_POINT + 4;
+        assert!(error(elf).contains("entry point"));
+    }
+
+    #[test]
+    fn rejects_segments_beyond_rom() {
+        let mut elf = TestElf::guest(&TEXT);
+        elf.segment_address = (ROM_BYTE_SIZE - 4) as u32;
+        assert!(error(elf).contains("does not fit into ROM"));
+    }
+
+    #[test]
+    fn rejects_text_that_differs_from_the_image() {
+        let mut elf = TestElf::guest(&TEXT);
+        elf.text[0] ^= 0xff;
+        assert!(error(elf).contains("does not match the ROM image"));
+
+        let mut elf = TestElf::guest(&TEXT);
+        elf.text_address = CUSTOM_ENTRY_POINT + 4;
+        assert!(error(elf).contains(".text starts at"));
+    }
+}
@@ -0,0 +1,226 @@
+//! Time-ordered flame chart of a simulator run, in the Chrome trace event format.
+//!
+//! The call stack is sampled every N cycles and consecutive samples are merged into begin/end
+//! events, so the chart shows which functions ran when. Timestamps are cycles: one microsecond
+//! in chrome://tracing or Perfetto is one cycle.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::callstack::CallStack;
+use crate::elf::SymbolTable;
+use crate::sim::{Step, StepAction, StepObserver};
+
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    /// `B` opens a frame, `E` closes the innermost open one.
+    ph: &'static str,
+    ts: usize,
+    pid: u32,
+    tid: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TraceFile {
+    trace_events: Vec<TraceEvent>,
+}
+
+pub struct FlameChart<'a> {
+    symbols: Option<&'a SymbolTable>,
+    path: PathBuf,
+    sampling_rate: usize,
+    call_stack: CallStack,
+    /// Functions of the last sample, outermost first, as function start addresses.
+    open_frames: Vec<u32>,
+    events: Vec<TraceEvent>,
+    samples: usize,
+    last_cycle: usize,
+}
+
+impl<'a> FlameChart<'a> {
+    pub fn new(path: PathBuf, symbols: Option<&'a SymbolTable>, sampling_rate: usize) -> Self {
+        Self {
+            symbols,
+            path,
+            sampling_rate: sampling_rate.max(1),
+            call_stack: CallStack::default(),
+            open_frames: Vec::new(),
+            events: Vec::new(),
+            samples: 0,
+            last_cycle: 0,
+        }
+    }
+
+    fn name(&self, function: u32) -> String {
+        match self.symbols.and_then(|symbols| symbols.lookup(function)) {
+            Some(symbol) => symbol.name.clone(),
+            None => format!("0x{function:08x}"),
+        }
+    }
+
+    fn event(&mut self, function: u32, ph: &'static str, ts: usize) {
+        self.events.push(TraceEvent {
+            name: self.name(function),
+            ph,
+            ts,
+            pid: 0,
+            tid: 0,
+        });
+    }
+
+    /// Closes frames of the previous sample that are gone and opens the new ones.
+    fn sample(&mut self, cycle: usize, frames: Vec<u32>) {
+        let common = self
+            .open_frames
+            .iter()
+            .zip(&frames)
+            .take_while(|(open, new)| open == new)
+            .count();
+        let closed: Vec<u32> = self.open_frames.drain(common..).rev().collect();
+        for function in closed {
+            self.event(function, "E", cycle);
+        }
+        for &function in &frames[common..] {
+            self.event(function, "B", cycle);
+        }
+        self.open_frames = frames;
+    }
+
+    /// Closes the frames still open at the end of the run and writes the chart.
+    pub fn write(mut self) -> Result<()> {
+        self.sample(self.last_cycle, Vec::new());
+        let file = TraceFile {
+            trace_events: self.events,
+        };
+        let encoded = serde_json::to_string(&file)?;
+        fs::write(&self.path, encoded)
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        tracing::info!(
+            "Flame chart with {} samples written to {}",
+            self.samples,
+            self.path.display()
+        );
+        Ok(())
+    }
+}
+
+impl StepObserver for FlameChart<'_> {
+    fn on_step(&mut self, step: &Step<'_>) -> StepAction {
+        self.last_cycle = step.cycle;
+        if step.cycle.is_multiple_of(self.sampling_rate) {
+            let frames = self.call_stack.functions(self.symbols, step.pc);
+            self.sample(step.cycle, frames);
+            self.samples += 1;
+        }
+        self.call_stack.on_step(step);
+        StepAction::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::tests::symbol_table;
+    use crate::riscv::{self, tests::RET, tests::jal_ra};
+
+    const NOP: u32 = 0x0000_0013;
+
+    struct Run<'a> {
+        chart: FlameChart<'a>,
+        registers: [u32; 32],
+    }
+
+    impl Run<'_> {
+        fn step(&mut self, cycle: usize, pc: u32, instruction: u32) {
+            self.chart.on_step(&Step {
+                cycle,
+                pc,
+                instruction,
+                registers: &self.registers,
+                memory: &[],
+            });
+        }
+
+        fn call(&mut self, cycle: usize, pc: u32, target: u32) {
+            self.step(cycle, pc, jal_ra(target.wrapping_sub(pc) as i32));
+            self.registers[riscv::RA as usize] = pc + 4;
+        }
+
+        fn ret(&mut self, cycle: usize, pc: u32, return_address: u32) {
+            self.registers[riscv::RA as usize] = return_address;
+            self.step(cycle, pc, RET);
+        }
+    }
+
+    #[test]
+    fn pairs_frames_across_samples() {
+        let path = std::env::temp_dir().join(format!(
+            "airbender-cli-{}-flame-chart.json",
+            std::process::id()
+        ));
+        let symbols = symbol_table(
+            &[
+                ("main", 0x100, 0x100),
+                ("foo", 0x200, 0x100),
+                ("bar", 0x300, 0x100),
+                ("baz", 0x400, 0x100),
+            ],
+            &[],
+        );
+        let mut run = Run {
+            chart: FlameChart::new(path.clone(), Some(&symbols), 4),
+            registers: [0; 32],
+        };
+        run.step(0, 0x100, NOP);
+        run.call(1, 0x104, 0x200);
+        run.call(2, 0x200, 0x300);
+        run.step(3, 0x300, NOP);
+        run.ret(4, 0x304, 0x204);
+        // bar returned and foo called baz between two samples.
+        run.call(5, 0x204, 0x400);
+        run.step(6, 0x400, NOP);
+        run.step(7, 0x404, NOP);
+        run.ret(8, 0x408, 0x108);
+        run.step(9, 0x108, NOP);
+        run.step(10, 0x10c, NOP);
+        run.step(11, 0x110, NOP);
+        run.step(12, 0x114, NOP);
+        run.step(13, 0x118, NOP);
+        run.chart.write().unwrap();
+        let json = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let trace: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let events: Vec<(String, String, u64)> = trace["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| {
+                (
+                    event["name"].as_str().unwrap().to_string(),
+                    event["ph"].as_str().unwrap().to_string(),
+                    event["ts"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        let expected = [
+            ("main", "B", 0),
+            ("foo", "B", 4),
+            ("bar", "B", 4),
+            ("bar", "E", 8),
+            ("baz", "B", 8),
+            ("baz", "E", 12),
+            ("foo", "E", 12),
+            ("main", "E", 13),
+        ];
+        let expected: Vec<(String, String, u64)> = expected
+            .iter()
+            .map(|&(name, ph, ts)| (name.to_string(), ph.to_string(), ts))
+            .collect();
+        assert_eq!(events, expected);
+    }
+}
@@ -0,0 +1,262 @@
+//! Flamegraphs rendered from sampled guest stacks, with focus, exclusion and depth filters.
+//!
+//! The simulator's own profiler renders the SVG in one go, so filtered graphs are built here
+//! instead: stacks are sampled from a shadow call stack, filtered as folded stacks and rendered
+//! with inferno. Frames come from the ELF symbol table, so inlined functions are not shown.
+
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::callstack::CallStack;
+use crate::elf::SymbolTable;
+use crate::sim::{Step, StepAction, StepObserver};
+
+/// Which part of the sampled stacks ends up in the flamegraph.
+#[derive(Debug, Default)]
+pub struct FlamegraphFilter {
+    /// Keeps only stacks through a matching frame, rooted at the outermost match.
+    pub focus: Option<Regex>,
+    /// Drops stacks through a matching frame.
+    pub exclude: Option<Regex>,
+    pub max_depth: Option<usize>,
+    /// Drops frames with fewer samples than this share of the total.
+    pub min_percent: Option<f64>,
+    /// Crates whose consecutive frames are merged into a single frame named after the crate.
+    pub collapse_crates: Vec<String>,
+}
+
+impl FlamegraphFilter {
+    pub fn is_empty(&self) -> bool {
+        self.focus.is_none()
+            && self.exclude.is_none()
+            && self.max_depth.is_none()
+            && self.min_percent.is_none()
+            && self.collapse_crates.is_empty()
+    }
+
+    /// Crate the frame is collapsed into, if any; trait impls (`<core::... as ...>`) count too.
+    fn collapsed_crate(&self, frame: &str) -> Option<&str> {
+        let path = frame.strip_prefix('<').unwrap_or(frame);
+        self.collapse_crates
+            .iter()
+            .find(|name| {
+                path.strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .map(String::as_str)
+    }
+
+    /// Applies everything but `min_percent` to one stack, outermost frame first.
+    fn apply(&self, stack: Vec<String>) -> Option<Vec<String>> {
+        let mut frames: Vec<String> = Vec::with_capacity(stack.len());
+        for frame in stack {
+            match self.collapsed_crate(&frame) {
+                Some(name) if frames.last().is_some_and(|last| last == name) => {}
+                Some(name) => frames.push(name.to_string()),
+                None => frames.push(frame),
+            }
+        }
+
+        if let Some(exclude) = &self.exclude
+            && frames.iter().any(|frame| exclude.is_match(frame))
+        {
+            return None;
+        }
+        if let Some(focus) = &self.focus {
+            let root = frames.iter().position(|frame| focus.is_match(frame))?;
+            frames.drain(..root);
+        }
+        if let Some(max_depth) = self.max_depth {
+            frames.truncate(max_depth);
+        }
+        Some(frames)
+    }
+}
+
+/// Step observer that counts the call stack every `sampling_rate` cycles.
+pub struct StackSampler<'a> {
+    symbols: Option<&'a SymbolTable>,
+    sampling_rate: usize,
+    call_stack: CallStack,
+    samples: HashMap<Vec<u32>, u64>,
+}
+
+impl<'a> StackSampler<'a> {
+    pub fn new(symbols: Option<&'a SymbolTable>, sampling_rate: usize) -> Self {
+        Self {
+            symbols,
+            sampling_rate: sampling_rate.max(1),
+            call_stack: CallStack::default(),
+            samples: HashMap::new(),
+        }
+    }
+
+    fn name(&self, function: u32) -> String {
+        let name = match self.symbols.and_then(|symbols| symbols.lookup(function)) {
+            Some(symbol) => symbol.name.clone(),
+            None => format!("0x{function:08x}"),
+        };
+        // `;` separates frames in folded stacks but shows up in array types.
+        name.replace(';', ",")
+    }
+
+    /// Filters the sampled stacks and renders them as an SVG flamegraph.
+    pub fn write_svg(&self, filter: &FlamegraphFilter, inverse: bool, path: &Path) -> Result<()> {
+        let mut stacks: BTreeMap<Vec<String>, u64> = BTreeMap::new();
+        for (functions, count) in &self.samples {
+            let stack = functions
+                .iter()
+                .map(|&function| self.name(function))
+                .collect();
+            if let Some(stack) = filter.apply(stack)
+                && !stack.is_empty()
+            {
+                *stacks.entry(stack).or_default() += count;
+            }
+        }
+        if let Some(min_percent) = filter.min_percent {
+            stacks = prune(stacks, min_percent);
+        }
+        if stacks.is_empty() {
+            bail!("no samples left after filtering the flamegraph");
+        }
+
+        let lines: Vec<String> = stacks
+            .iter()
+            .map(|(stack, count)| format!("{} {count}", stack.join(";")))
+            .collect();
+        let mut options = inferno::flamegraph::Options::default();
+        options.count_name = "samples".to_string();
+        options.reverse_stack_order = inverse;
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        inferno::flamegraph::from_lines(
+            &mut options,
+            lines.iter().map(String::as_str),
+            BufWriter::new(file),
+        )
+        .with_context(|| format!("failed to write flamegraph to {}", path.display()))?;
+        tracing::info!(
+            "Flamegraph with {} samples written to {}",
+            stacks.values().sum::<u64>(),
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+impl StepObserver for StackSampler<'_> {
+    fn on_step(&mut self, step: &Step<'_>) -> StepAction {
+        if step.cycle.is_multiple_of(self.sampling_rate) {
+            let functions = self.call_stack.functions(self.symbols, step.pc);
+            *self.samples.entry(functions).or_default() += 1;
+        }
+        self.call_stack.on_step(step);
+        StepAction::Continue
+    }
+}
+
+/// Cuts every stack at its first frame below `min_percent` of all samples; the samples still
+/// count towards the callers, so their widths stay the same.
+fn prune(stacks: BTreeMap<Vec<String>, u64>, min_percent: f64) -> BTreeMap<Vec<String>, u64> {
+    let total: u64 = stacks.values().sum();
+    let threshold = total as f64 * min_percent / 100.0;
+    let mut frame_totals: HashMap<&[String], u64> = HashMap::new();
+    for (stack, count) in &stacks {
+        for depth in 1..=stack.len() {
+            *frame_totals.entry(&stack[..depth]).or_default() += count;
+        }
+    }
+
+    let mut pruned: BTreeMap<Vec<String>, u64> = BTreeMap::new();
+    for (stack, count) in &stacks {
+        let depth = (1..=stack.len())
+            .find(|&depth| (frame_totals[&stack[..depth]] as f64) < threshold)
+            .map_or(stack.len(), |depth| depth - 1);
+        if depth > 0 {
+            *pruned.entry(stack[..depth].to_vec()).or_default() += count;
+        }
+    }
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(frames: &[&str]) -> Vec<String> {
+        frames.iter().map(|frame| frame.to_string()).collect()
+    }
+
+    fn regex(pattern: &str) -> Option<Regex> {
+        Some(Regex::new(pattern).unwrap())
+    }
+
+    #[test]
+    fn focus_roots_stacks_at_the_outermost_match() {
+        let filter = FlamegraphFilter {
+            focus: regex("hash"),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.apply(stack(&["main", "hash_block", "hash_round", "mix"])),
+            Some(stack(&["hash_block", "hash_round", "mix"]))
+        );
+        assert_eq!(filter.apply(stack(&["main", "verify"])), None);
+    }
+
+    #[test]
+    fn exclude_drops_matching_stacks() {
+        let filter = FlamegraphFilter {
+            exclude: regex("memcpy"),
+            ..Default::default()
+        };
+        assert_eq!(filter.apply(stack(&["main", "memcpy"])), None);
+        assert_eq!(
+            filter.apply(stack(&["main", "verify"])),
+            Some(stack(&["main", "verify"]))
+        );
+    }
+
+    #[test]
+    fn collapses_crates_before_cutting_to_max_depth() {
+        let filter = FlamegraphFilter {
+            max_depth: Some(3),
+            collapse_crates: vec!["core".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.apply(stack(&[
+                "main",
+                "core::fmt::write",
+                "<core::fmt::Arguments as core::fmt::Display>::fmt",
+                "core_crypto::hash",
+                "leaf",
+            ])),
+            Some(stack(&["main", "core", "core_crypto::hash"]))
+        );
+    }
+
+    #[test]
+    fn prune_cuts_rare_frames_but_keeps_their_samples() {
+        let stacks = BTreeMap::from([
+            (stack(&["main", "hot"]), 90),
+            (stack(&["main", "cold"]), 5),
+            (stack(&["main", "hot", "rare"]), 5),
+        ]);
+        assert_eq!(
+            prune(stacks, 10.0),
+            BTreeMap::from([(stack(&["main"]), 5), (stack(&["main", "hot"]), 95)])
+        );
+    }
+
+    #[test]
+    fn prune_drops_stacks_below_the_threshold_at_the_root() {
+        let stacks = BTreeMap::from([(stack(&["main"]), 99), (stack(&["init"]), 1)]);
+        assert_eq!(prune(stacks, 5.0), BTreeMap::from([(stack(&["main"]), 99)]));
+    }
+}
@@ -0,0 +1,500 @@
+//! Mutation-based fuzzing of guest input word streams.
+//!
+//! The simulator reports the executed PCs used as coverage feedback, detects traps, and its
+//! failures are host panics that can be caught. The JIT transpiler is much faster but has neither
+//! coverage nor trap detection, so with `--engine jit` it only screens inputs: runs that end
+//! differently from every earlier one are re-run in the simulator, which decides what they cover
+//! and whether they are findings. A crash in generated code still takes the process down.
+
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::cli::{Engine, InputExhausted, Machine};
+use crate::console;
+use crate::input;
+use crate::program::Program;
+use crate::sim::{self, SimulationOutcome, SimulatorOptions, Step, StepAction, StepObserver};
+use crate::sim_transpiler::{self, JIT_MACHINE, TranspilerOptions};
+
+/// Values that tend to hit edge cases in length fields and arithmetic.
+const INTERESTING_WORDS: [u32; 9] = [
+    0,
+    1,
+    2,
+    0x7f,
+    0xff,
+    0xffff,
+    0x7fff_ffff,
+    0x8000_0000,
+    u32::MAX,
+];
+
+/// Default cycle limit of a single fuzzing run.
+pub const DEFAULT_FUZZ_CYCLES: usize = 100_000_000;
+
+/// Upper bound on the length of mutated inputs, in words.
+const MAX_INPUT_WORDS: usize = 1 << 16;
+
+pub struct FuzzOptions {
+    /// Engine every input runs on; the simulator still runs the ones the JIT flags.
+    pub engine: Engine,
+    pub machine: Machine,
+    pub iterations: u64,
+    /// Hard cycle limit of each run.
+    pub cycles: usize,
+    /// Runs above this many cycles are reported as slow.
+    pub cycle_threshold: Option<usize>,
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Finding {
+    Trap,
+    Panic,
+    HostPanic,
+    CycleLimit,
+    Slow,
+}
+
+impl Finding {
+    fn name(self) -> &'static str {
+        match self {
+            Finding::Trap => "trap",
+            Finding::Panic => "panic",
+            Finding::HostPanic => "host-panic",
+            Finding::CycleLimit => "cycle-limit",
+            Finding::Slow => "slow",
+        }
+    }
+}
+
+/// Mutates the inputs in `corpus_dir` and saves the ones that trap, panic or run too long to
+/// `out_dir`, in the format `--input` reads.
+pub fn fuzz(
+    program: &Program,
+    corpus_dir: &Path,
+    out_dir: &Path,
+    options: FuzzOptions,
+) -> Result<()> {
+    if options.engine == Engine::Jit && options.machine != JIT_MACHINE {
+        bail!(
+            "--engine jit only implements {JIT_MACHINE}, got {}; use --engine sim",
+            options.machine
+        );
+    }
+    let mut corpus = load_corpus(corpus_dir)?;
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("failed to create {}", out_dir.display()))?;
+    tracing::info!(
+        "Fuzzing {} with {} seed inputs ({} engine, {} iterations)",
+        program.path.display(),
+        corpus.len(),
+        options.engine,
+        options.iterations
+    );
+
+    tracing::info!("Using seed {}", options.seed);
+    let mut rng = Rng::new(options.seed);
+    let mut covered = PcSet::default();
+    let mut seen: HashSet<(Finding, u64)> = HashSet::new();
+    let mut behaviours: HashSet<u64> = HashSet::new();
+    let mut simulated = 0u64;
+    let mut findings = 0usize;
+    let start = Instant::now();
+
+    // Seeds run unmodified first, so they establish the coverage baseline.
+    let seeds = corpus.clone();
+    for iteration in 0..seeds.len() as u64 + options.iterations {
+        let input_words = match seeds.get(iteration as usize) {
+            Some(seed) => seed.clone(),
+            None => mutate(&corpus, &mut rng),
+        };
+
+        if options.engine == Engine::Jit {
+            let screen = execute_jit(program, &input_words, &options);
+            // Runs that end like an earlier one most likely took the same path.
+            if !behaviours.insert(behaviour(&screen)) {
+                continue;
+            }
+        }
+        simulated += 1;
+        let mut pcs = PcSet::default();
+        let result = execute(program, &input_words, &options, &mut pcs);
+        if covered.merge(&pcs) && iteration >= seeds.len() as u64 {
+            tracing::debug!("Input {iteration} reached new code, adding it to the corpus");
+            corpus.push(input_words.clone());
+        }
+
+        let Some((finding, signature)) = classify(&result, &pcs, &options) else {
+            continue;
+        };
+        if !seen.insert((finding, signature)) {
+            continue;
+        }
+        findings += 1;
+        let path = out_dir.join(format!("{}-{iteration}.hex", finding.name()));
+        save_input(&path, &input_words)?;
+        match &result {
+            Ok(outcome) => tracing::warn!(
+                "Found {} after {} cycles, saved to {}",
+                finding.name(),
+                outcome.cycles_executed,
+                path.display()
+            ),
+            Err(message) => tracing::warn!(
+                "Found {}: {message}, saved to {}",
+                finding.name(),
+                path.display()
+            ),
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    tracing::info!(
+        "Fuzzing finished in {elapsed:.1}s: {findings} unique findings, corpus grew to {} inputs",
+        corpus.len()
+    );
+    if options.engine == Engine::Jit {
+        tracing::info!(
+            "{simulated} of {} inputs were re-run in the simulator",
+            seeds.len() as u64 + options.iterations
+        );
+    }
+    tracing::info!("Executed {} distinct instructions", covered.count());
+    Ok(())
+}
+
+/// Runs one input; `Err` holds the message of a host-side panic.
+fn execute(
+    program: &Program,
+    input_words: &[u32],
+    options: &FuzzOptions,
+    pcs: &mut PcSet,
+) -> Result<SimulationOutcome, String> {
+    let null_device = Path::new(console::NULL_DEVICE);
+    let sim_options = SimulatorOptions {
+        console_output: Some(null_device.to_path_buf()),
+        machine: options.machine,
+        // Short inputs are part of the search space, not findings.
+        on_input_exhausted: InputExhausted::Zero,
+        detect_traps: true,
+        ..Default::default()
+    };
+    catch_panic(|| {
+        sim::run_simulator(
+            program,
+            input_words.to_vec(),
+            options.cycles,
+            sim_options,
+            &mut [pcs],
+        )
+    })
+}
+
+/// Runs one input on the JIT; `Err` holds the message of a host-side panic.
+fn execute_jit(
+    program: &Program,
+    input_words: &[u32],
+    options: &FuzzOptions,
+) -> Result<SimulationOutcome, String> {
+    let jit_options = TranspilerOptions {
+        console_output: Some(PathBuf::from(console::NULL_DEVICE)),
+        on_input_exhausted: InputExhausted::Zero,
+        machine: options.machine,
+        ..Default::default()
+    };
+    catch_panic(|| {
+        sim_transpiler::run_transpiler(program, input_words.to_vec(), options.cycles, jit_options)
+    })
+}
+
+fn catch_panic(
+    run: impl FnOnce() -> Result<SimulationOutcome>,
+) -> Result<SimulationOutcome, String> {
+    match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(Ok(outcome)) => Ok(outcome),
+        Ok(Err(err)) => Err(format!("{err:#}")),
+        Err(payload) => Err(payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "unknown panic".to_string())),
+    }
+}
+
+/// Returns the kind of finding and a signature used to report each distinct failure once.
+fn classify(
+    result: &Result<SimulationOutcome, String>,
+    pcs: &PcSet,
+    options: &FuzzOptions,
+) -> Option<(Finding, u64)> {
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(message) => return Some((Finding::HostPanic, fingerprint(message.as_bytes()))),
+    };
+    if let Some(trap) = &outcome.trap {
+        return Some((Finding::Trap, u64::from(trap.backtrace[0].pc)));
+    }
+    if let Some(panic) = &outcome.panic {
+        let location = panic.location.as_deref().unwrap_or(&panic.message);
+        return Some((Finding::Panic, fingerprint(location.as_bytes())));
+    }
+    if !outcome.reached_end || outcome.cycles_executed >= options.cycles {
+        // Keyed on the code the run reached, so different hangs are reported separately.
+        return Some((Finding::CycleLimit, pcs.fingerprint()));
+    }
+    match options.cycle_threshold {
+        // Bucket by powers of two so slower inputs are still reported.
+        Some(threshold) if outcome.cycles_executed > threshold => {
+            Some((Finding::Slow, u64::from(outcome.cycles_executed.ilog2())))
+        }
+        _ => None,
+    }
+}
+
+/// Summary of how a JIT run ended: its status and its cycle count, rounded down to a power of two.
+fn behaviour(result: &Result<SimulationOutcome, String>) -> u64 {
+    match result {
+        Err(message) => fingerprint(message.as_bytes()),
+        Ok(outcome) => {
+            let status = outcome.status() as u64;
+            (status << 32) | u64::from(outcome.cycles_executed.max(1).ilog2())
+        }
+    }
+}
+
+fn load_corpus(dir: &Path) -> Result<Vec<Vec<u32>>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("failed to read corpus directory {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+
+    let corpus = paths
+        .iter()
+        .map(|path| input::parse_input_words(path))
+        .collect::<Result<Vec<_>>>()?;
+    if corpus.is_empty() {
+        bail!("corpus directory {} is empty", dir.display());
+    }
+    Ok(corpus)
+}
+
+fn save_input(path: &Path, words: &[u32]) -> Result<()> {
+    let mut hex = String::with_capacity(words.len() * 9);
+    for word in words {
+        hex.push_str(&format!("{word:08x}\n"));
+    }
+    fs::write(path, hex).with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn mutate(corpus: &[Vec<u32>], rng: &mut Rng) -> Vec<u32> {
+    let mut words = corpus[rng.below(corpus.len())].clone();
+    for _ in 0..1 + rng.below(4) {
+        match rng.below(7) {
+            0 if !words.is_empty() => {
+                let idx = rng.below(words.len());
+                words[idx] ^= 1 << rng.below(32);
+            }
+            1 if !words.is_empty() => {
+                let idx = rng.below(words.len());
+                words[idx] = INTERESTING_WORDS[rng.below(INTERESTING_WORDS.len())];
+            }
+            2 if !words.is_empty() => {
+                let idx = rng.below(words.len());
+                let delta = rng.below(33) as i32 - 16;
+                words[idx] = words[idx].wrapping_add_signed(delta);
+            }
+            3 => {
+                let idx = rng.below(words.len() + 1);
+                words.insert(idx, rng.next() as u32);
+            }
+            4 if !words.is_empty() => {
+                words.remove(rng.below(words.len()));
+            }
+            5 if !words.is_empty() => {
+                words.truncate(rng.below(words.len()));
+            }
+            6 => {
+                // Splice the tail of another corpus entry.
+                let other = &corpus[rng.below(corpus.len())];
+                let cut = rng.below(words.len() + 1);
+                let other_cut = rng.below(other.len() + 1);
+                words.truncate(cut);
+                words.extend_from_slice(&other[other_cut..]);
+            }
+            _ => {}
+        }
+    }
+    words.truncate(MAX_INPUT_WORDS);
+    words
+}
+
+fn fingerprint(bytes: &[u8]) -> u64 {
+    // FNV-1a
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Set of executed PCs, used as coverage feedback.
+#[derive(Default)]
+struct PcSet {
+    /// One bit per instruction, indexed by `pc / 4`.
+    bits: Vec<u64>,
+}
+
+impl PcSet {
+    /// Adds `other`; returns whether it contained PCs not seen before.
+    fn merge(&mut self, other: &PcSet) -> bool {
+        if self.bits.len() < other.bits.len() {
+            self.bits.resize(other.bits.len(), 0);
+        }
+        let mut grew = false;
+        for (bits, other) in self.bits.iter_mut().zip(&other.bits) {
+            grew |= other & !*bits != 0;
+            *bits |= other;
+        }
+        grew
+    }
+
+    fn count(&self) -> u32 {
+        self.bits.iter().map(|bits| bits.count_ones()).sum()
+    }
+
+    fn fingerprint(&self) -> u64 {
+        // Trailing empty words depend on the highest PC seen so far, not on the set.
+        let used = self
+            .bits
+            .iter()
+            .rposition(|&bits| bits != 0)
+            .map_or(0, |idx| idx + 1);
+        let bytes: Vec<u8> = self.bits[..used]
+            .iter()
+            .flat_map(|bits| bits.to_le_bytes())
+            .collect();
+        fingerprint(&bytes)
+    }
+}
+
+impl StepObserver for PcSet {
+    fn on_step(&mut self, step: &Step<'_>) -> StepAction {
+        let idx = (step.pc / 4) as usize;
+        if idx / 64 >= self.bits.len() {
+            self.bits.resize(idx / 64 + 1, 0);
+        }
+        self.bits[idx / 64] |= 1 << (idx % 64);
+        StepAction::Continue
+    }
+}
+
+/// xorshift64*, good enough for picking mutations.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcs(list: &[u32]) -> PcSet {
+        let mut pcs = PcSet::default();
+        for &pc in list {
+            let idx = (pc / 4) as usize;
+            if idx / 64 >= pcs.bits.len() {
+                pcs.bits.resize(idx / 64 + 1, 0);
+            }
+            pcs.bits[idx / 64] |= 1 << (idx % 64);
+        }
+        pcs
+    }
+
+    fn outcome(cycles_executed: usize, reached_end: bool) -> SimulationOutcome {
+        SimulationOutcome {
+            registers: [0; 32],
+            cycles_executed,
+            reached_end,
+            markers: Vec::new(),
+            panic: None,
+            trap: None,
+            input_words: Some(0),
+            oracle_reads: Vec::new(),
+            input_exhausted: false,
+        }
+    }
+
+    fn options() -> FuzzOptions {
+        FuzzOptions {
+            engine: Engine::Sim,
+            machine: Machine::default(),
+            iterations: 0,
+            cycles: 1000,
+            cycle_threshold: Some(100),
+            seed: 1,
+        }
+    }
+
+    #[test]
+    fn cycle_limits_are_keyed_on_the_code_reached() {
+        let hung = Ok(outcome(1000, false));
+        let loop_a = classify(&hung, &pcs(&[0, 4, 8]), &options()).unwrap();
+        let loop_b = classify(&hung, &pcs(&[0, 4, 0x400]), &options()).unwrap();
+        assert_eq!(loop_a.0, Finding::CycleLimit);
+        assert_eq!(loop_b.0, Finding::CycleLimit);
+        assert_ne!(loop_a.1, loop_b.1);
+        assert_eq!(classify(&hung, &pcs(&[0, 4, 8]), &options()), Some(loop_a));
+    }
+
+    #[test]
+    fn coverage_fingerprint_ignores_unused_words() {
+        let mut grown = pcs(&[0, 4]);
+        grown.merge(&PcSet { bits: vec![0; 4] });
+        assert_eq!(grown.bits.len(), 4);
+        assert_eq!(grown.fingerprint(), pcs(&[0, 4]).fingerprint());
+        assert_ne!(grown.fingerprint(), pcs(&[0, 8]).fingerprint());
+    }
+
+    #[test]
+    fn slow_runs_are_bucketed_by_powers_of_two() {
+        let covered = pcs(&[0]);
+        assert_eq!(
+            classify(&Ok(outcome(100, true)), &covered, &options()),
+            None
+        );
+        let slow = classify(&Ok(outcome(300, true)), &covered, &options());
+        assert_eq!(slow, Some((Finding::Slow, 8)));
+        assert_eq!(
+            classify(&Ok(outcome(400, true)), &covered, &options()),
+            slow
+        );
+    }
+
+    #[test]
+    fn jit_behaviour_separates_status_and_cycle_buckets() {
+        let finished = behaviour(&Ok(outcome(300, true)));
+        assert_eq!(behaviour(&Ok(outcome(400, true))), finished);
+        assert_ne!(behaviour(&Ok(outcome(600, true))), finished);
+        assert_ne!(behaviour(&Ok(outcome(300, false))), finished);
+        assert_ne!(behaviour(&Err("boom".to_string())), finished);
+    }
+}
@@ -0,0 +1,248 @@
+//! Input oracle backed by a host helper process.
+//!
+//! The helper is spawned once per run and talks line by line over its stdin/stdout:
+//!
+//! - `R` asks for the next input word; the helper answers with one line holding the word in
+//!   hex (`0x` prefix optional).
+//! - `W <word>` forwards a word the guest wrote to the oracle, in hex; it gets no answer.
+//!
+//! Anything the helper writes to stderr goes to the terminal.
+
+use anyhow::{Context, Result, anyhow, bail};
+use risc_v_simulator::abstractions::memory::MemorySource;
+use risc_v_simulator::abstractions::non_determinism::{NonDeterminismCSRSource, QuasiUARTSource};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// Helper command line, split into words like a POSIX shell would, without expansions.
+#[derive(Debug, Clone)]
+pub struct OracleCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+pub fn parse_oracle_command(value: &str) -> Result<OracleCommand, String> {
+    let mut parts = shell_words::split(value)
+        .map_err(|err| format!("invalid oracle command: {err}"))?
+        .into_iter();
+    let program = parts
+        .next()
+        .ok_or_else(|| "oracle command is empty".to_string())?;
+    Ok(OracleCommand {
+        program,
+        args: parts.collect(),
+    })
+}
+
+pub struct ProcessOracle {
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    /// First protocol error; later reads return zero and the run fails once it is over.
+    error: Option<String>,
+}
+
+impl ProcessOracle {
+    pub fn spawn(command: &OracleCommand) -> Result<Self> {
+        let mut child = Command::new(&command.program)
+            .args(&command.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to start oracle helper {}", command.program))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(Self {
+            child,
+            stdin: BufWriter::new(stdin),
+            stdout: BufReader::new(stdout),
+            error: None,
+        })
+    }
+
+    fn request(&mut self) -> Result<u32> {
+        self.stdin.write_all(b"R\n")?;
+        self.stdin.flush()?;
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            bail!("oracle helper closed its output");
+        }
+        let word = line.trim();
+        let digits = word.strip_prefix("0x").unwrap_or(word);
+        u32::from_str_radix(digits, 16)
+            .map_err(|_| anyhow!("oracle helper sent an invalid word: {word:?}"))
+    }
+
+    fn send(&mut self, value: u32) -> Result<()> {
+        writeln!(self.stdin, "W {value:08x}")?;
+        Ok(())
+    }
+
+    fn fail(&mut self, err: anyhow::Error) {
+        if self.error.is_none() {
+            tracing::error!("Oracle helper failed: {err:#}");
+            self.error = Some(format!("{err:#}"));
+        }
+    }
+}
+
+impl Drop for ProcessOracle {
+    fn drop(&mut self) {
+        // The run is over, so the helper has nothing left to answer.
+        let _ = self.stdin.flush();
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The oracle a run reads its input from.
+pub enum InputOracle {
+    Words {
+        source: QuasiUARTSource,
+        /// Set once the guest read past the end of the words; such reads return zero.
+        ran_dry: bool,
+    },
+    Process(ProcessOracle),
+}
+
+impl InputOracle {
+    pub fn new(input_words: Vec<u32>, command: Option<&OracleCommand>) -> Result<Self> {
+        Ok(match command {
+            Some(command) => InputOracle::Process(ProcessOracle::spawn(command)?),
+            None => InputOracle::Words {
+                source: QuasiUARTSource::new_with_reads(input_words),
+                ran_dry: false,
+            },
+        })
+    }
+
+    /// Fails if the helper process broke the protocol during the run.
+    pub fn check(&self) -> Result<()> {
+        match self {
+            InputOracle::Process(ProcessOracle {
+                error: Some(error), ..
+            }) => bail!("oracle helper failed: {error}"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Input words not read yet, if they are known up front.
+    pub fn remaining_words(&self) -> Option<Vec<u32>> {
+        match self {
+            InputOracle::Words { source, .. } => Some(source.oracle.iter().copied().collect()),
+            InputOracle::Process(_) => None,
+        }
+    }
+
+    /// Whether the next read would run past the end of the input. Always false for a helper
+    /// process, which answers every read; `--on-input-exhausted` is rejected together with one.
+    pub fn is_exhausted(&self) -> bool {
+        matches!(self, InputOracle::Words { source, .. } if source.oracle.is_empty())
+    }
+
+    /// Whether the guest read past the end of the input; never the case for a helper process.
+    pub fn ran_dry(&self) -> bool {
+        matches!(self, InputOracle::Words { ran_dry: true, .. })
+    }
+}
+
+impl<M: MemorySource> NonDeterminismCSRSource<M> for InputOracle {
+    fn read(&mut self) -> u32 {
+        match self {
+            InputOracle::Words { source, ran_dry } => {
+                if source.oracle.is_empty() {
+                    *ran_dry = true;
+                    return 0;
+                }
+                NonDeterminismCSRSource::<M>::read(source)
+            }
+            InputOracle::Process(process) => {
+                if process.error.is_some() {
+                    return 0;
+                }
+                process.request().unwrap_or_else(|err| {
+                    process.fail(err);
+                    0
+                })
+            }
+        }
+    }
+
+    fn write_with_memory_access(&mut self, memory: &M, value: u32) {
+        match self {
+            InputOracle::Words { source, .. } => source.write_with_memory_access(memory, value),
+            InputOracle::Process(process) => {
+                if process.error.is_some() {
+                    return;
+                }
+                if let Err(err) = process.send(value) {
+                    process.fail(err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(script: &str) -> OracleCommand {
+        OracleCommand {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+        }
+    }
+
+    #[test]
+    fn splits_commands_like_a_shell() {
+        let command = parse_oracle_command(r#"python3 'my helper.py' --name "a b" c\ d"#).unwrap();
+        assert_eq!(command.program, "python3");
+        assert_eq!(command.args, ["my helper.py", "--name", "a b", "c d"]);
+        assert!(parse_oracle_command("  ").is_err());
+        assert!(parse_oracle_command("helper 'unterminated").is_err());
+    }
+
+    #[test]
+    fn reads_answers_and_forwards_writes() {
+        // Answers each read with the last word written, starting at 0x2a.
+        let command = script(
+            "last=0000002a; while read cmd arg; do case $cmd in \
+             R) echo $last;; W) last=$arg;; esac; done",
+        );
+        let mut process = ProcessOracle::spawn(&command).unwrap();
+        assert_eq!(process.request().unwrap(), 0x2a);
+        process.send(0xdead_beef).unwrap();
+        assert_eq!(process.request().unwrap(), 0xdead_beef);
+        process.send(7).unwrap();
+        assert_eq!(process.request().unwrap(), 7);
+    }
+
+    #[test]
+    fn rejects_invalid_answers() {
+        // Closes its output after two answers but keeps reading, so requests see no broken pipe.
+        let command = script("read cmd; echo 0x1f; read cmd; echo zz; exec >&-; cat >/dev/null");
+        let mut process = ProcessOracle::spawn(&command).unwrap();
+        assert_eq!(process.request().unwrap(), 0x1f);
+        let message = process.request().unwrap_err().to_string();
+        assert!(message.contains("invalid word"), "{message}");
+        let message = process.request().unwrap_err().to_string();
+        assert!(message.contains("closed its output"), "{message}");
+    }
+
+    #[test]
+    fn reports_the_first_protocol_error() {
+        let command = script("read cmd; echo nope");
+        let mut oracle = InputOracle::new(Vec::new(), Some(&command)).unwrap();
+        let InputOracle::Process(process) = &mut oracle else {
+            unreachable!("a command spawns a process");
+        };
+        let err = process.request().unwrap_err();
+        process.fail(err);
+        process.fail(anyhow!("later failure"));
+        let message = oracle.check().unwrap_err().to_string();
+        assert!(message.contains("invalid word"), "{message}");
+        assert!(!oracle.ran_dry());
+        assert_eq!(oracle.remaining_words(), None);
+    }
+}
@@ -2,12 +2,40 @@ use anyhow::Result;
 use clap::Parser;
 use execution_utils::unrolled_gpu::UnrolledProverLevel;
 
+mod batch;
+mod bench;
+mod call;
+mod call_trace;
+mod callstack;
+mod check;
 mod cli;
+mod console;
+mod coverage;
+mod disasm;
+mod elf;
+mod flame_chart;
+mod flamegraph;
+mod fuzz;
+mod host_oracle;
 mod input;
+mod markers;
+mod memory_profile;
+mod oracle;
+mod pack;
+mod preload;
+mod program;
 mod prover;
+mod riscv;
 mod sim;
 mod sim_transpiler;
+mod size;
+mod snapshot;
+mod test_runner;
+mod transcript;
+mod trap;
 mod vk;
+mod watch;
+mod xml;
 
 fn main() -> Result<()> {
     init_tracing()?;
@@ -18,12 +46,225 @@ fn main() -> Result<()> {
             app_bin,
             input,
             cycles,
+            console_output,
+            machine,
+            coverage,
+            coverage_format,
+            snapshot_at,
+            snapshot_file,
+            resume,
+            oracle_command,
+            transcript_output,
+            on_input_exhausted,
+            load_memory,
+            entry,
+            registers,
+            watchpoints,
+            watch_stop,
+            memory_profile,
+            stack_heap_margin,
+            call_trace,
+            call_trace_depth,
+            call_trace_filter,
+            no_trap_detection,
         } => {
-            let input_words = input::parse_input_words(&input)?;
+            let resume = resume
+                .map(|path| snapshot::Snapshot::load(&path))
+                .transpose()?;
+            let input_words = match (&input, &resume) {
+                (Some(input), None) => input::parse_input_words(input)?,
+                (Some(_), Some(_)) => {
+                    tracing::warn!(
+                        "Ignoring --input: resuming with the snapshot's remaining input"
+                    );
+                    Vec::new()
+                }
+                (None, _) => Vec::new(),
+            };
             let cycle_limit = cycles.unwrap_or(sim::DEFAULT_CYCLES);
             tracing::info!("Running simulator");
-            let outcome = sim::run_simulator(&app_bin, input_words, cycle_limit, None)?;
+            let program = program::Program::load(&app_bin, None)?;
+            if coverage.is_some() {
+                coverage::require_debug_info(&program)?;
+            }
+            let call = entry
+                .map(|entry| call::FunctionCall::new(&program, &entry, &registers))
+                .transpose()?;
+            let report_call = call.is_some();
+            let options = sim::SimulatorOptions {
+                console_output,
+                machine,
+                snapshot: snapshot_at
+                    .zip(snapshot_file)
+                    .map(|(cycle, path)| snapshot::SnapshotRequest { cycle, path }),
+                resume,
+                oracle_command,
+                on_input_exhausted,
+                preload: preload::load(&load_memory)?,
+                call,
+                detect_traps: !no_trap_detection,
+                ..Default::default()
+            };
+            let mut recorder = coverage::CoverageRecorder::default();
+            let mut watcher =
+                watch::Watcher::new(watchpoints, program.symbols.as_ref(), watch_stop);
+            let watching = !watcher.is_empty();
+            let mut profiler = memory_profile.then(|| {
+                memory_profile::MemoryProfiler::new(program.symbols.as_ref(), stack_heap_margin)
+            });
+            let mut tracer = call_trace
+                .map(|path| {
+                    call_trace::CallTracer::create(
+                        &path,
+                        program.symbols.as_ref(),
+                        call_trace_depth,
+                        call_trace_filter,
+                    )
+                })
+                .transpose()?;
+            let mut observers: Vec<&mut dyn sim::StepObserver> = Vec::new();
+            if coverage.is_some() {
+                observers.push(&mut recorder);
+            }
+            if watching {
+                observers.push(&mut watcher);
+            }
+            if let Some(profiler) = &mut profiler {
+                observers.push(profiler);
+            }
+            if let Some(tracer) = &mut tracer {
+                observers.push(tracer);
+            }
+            let outcome =
+                sim::run_simulator(&program, input_words, cycle_limit, options, &mut observers)?;
             sim::report_run_outcome(&outcome);
+            if let Some(tracer) = tracer {
+                tracer.finish()?;
+            }
+            if watching {
+                watcher.report();
+            }
+            if let Some(profiler) = &profiler {
+                profiler.report();
+            }
+            if report_call {
+                call::report_return(&outcome);
+            }
+            if let Some(path) = transcript_output {
+                transcript::write(&outcome.oracle_reads, &path)?;
+            }
+            if let Some(coverage) = coverage {
+                coverage::write_report(&program, &recorder, &coverage, coverage_format)?;
+            }
+        }
+        cli::Commands::RunBatch {
+            app_bin,
+            inputs,
+            jobs,
+            engine,
+            cycles,
+            csv,
+            json,
+            console_dir,
+            machine,
+        } => {
+            let program = program::Program::load(&app_bin, None)?;
+            let options = batch::BatchOptions {
+                engine,
+                machine,
+                cycles: cycles.unwrap_or(sim::DEFAULT_CYCLES),
+                jobs: jobs
+                    .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+                console_dir,
+            };
+            let results = batch::run_batch(&program, &inputs, &options)?;
+            batch::report(&results);
+            if let Some(csv) = csv {
+                batch::write_csv(&results, &csv)?;
+            }
+            if let Some(json) = json {
+                batch::write_json(&results, &json)?;
+            }
+            let failed = results
+                .iter()
+                .filter(|result| !result.status.is_ok())
+                .count();
+            if failed > 0 {
+                anyhow::bail!("{failed} of {} inputs failed", results.len());
+            }
+        }
+        cli::Commands::Test { manifest, junit } => {
+            let results = test_runner::run_manifest(&manifest)?;
+            test_runner::report(&results);
+            if let Some(junit) = junit {
+                let suite = manifest
+                    .file_stem()
+                    .map_or("airbender".into(), |stem| stem.to_string_lossy());
+                test_runner::write_junit(&results, &suite, &junit)?;
+            }
+            let failed = results.iter().filter(|result| !result.passed()).count();
+            if failed > 0 {
+                anyhow::bail!("{failed} of {} test cases failed", results.len());
+            }
+        }
+        cli::Commands::Coverage {
+            app_bin,
+            inputs,
+            output,
+            format,
+            cycles,
+            machine,
+        } => {
+            let cycle_limit = cycles.unwrap_or(sim::DEFAULT_CYCLES);
+            let program = program::Program::load(&app_bin, None)?;
+            coverage::require_debug_info(&program)?;
+            let mut total = coverage::CoverageRecorder::default();
+            for input in &inputs {
+                let input_words = input::parse_input_words(input)?;
+                let options = sim::SimulatorOptions {
+                    machine,
+                    ..Default::default()
+                };
+                let mut recorder = coverage::CoverageRecorder::default();
+                tracing::info!("Running simulator on {}", input.display());
+                let outcome = sim::run_simulator(
+                    &program,
+                    input_words,
+                    cycle_limit,
+                    options,
+                    &mut [&mut recorder],
+                )?;
+                sim::report_run_outcome(&outcome);
+                total.merge(&recorder);
+            }
+            coverage::write_report(&program, &total, &output, format)?;
+        }
+        cli::Commands::Fuzz {
+            app_bin,
+            corpus,
+            out_dir,
+            engine,
+            iterations,
+            cycles,
+            cycle_threshold,
+            seed,
+            machine,
+        } => {
+            let program = program::Program::load(&app_bin, None)?;
+            let seed = seed.unwrap_or_else(|| {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(1, |elapsed| elapsed.as_nanos() as u64)
+            });
+            let options = fuzz::FuzzOptions {
+                engine,
+                machine,
+                iterations,
+                cycles: cycles.unwrap_or(fuzz::DEFAULT_FUZZ_CYCLES),
+                cycle_threshold,
+                seed,
+            };
+            fuzz::fuzz(&program, &corpus, &out_dir, options)?;
         }
         cli::Commands::Flamegraph {
             app_bin,
@@ -32,80 +273,242 @@ fn main() -> Result<()> {
             cycles,
             sampling_rate,
             inverse,
+            flame_chart,
+            focus,
+            exclude,
+            max_depth,
+            min_percent,
+            collapse_crates,
             elf_path,
+            console_output,
+            machine,
+            watchpoints,
+            watch_stop,
         } => {
             let input_words = input::parse_input_words(&input)?;
             let cycle_limit = cycles.unwrap_or(sim::DEFAULT_CYCLES);
+            let mut program = program::Program::load(&app_bin, None)?;
+            if let Some(elf_path) = elf_path {
+                program.set_elf_path(elf_path)?;
+            }
             let diagnostics =
-                sim::profiler_diagnostics(&app_bin, elf_path, output, sampling_rate, inverse)?;
+                sim::profiler_diagnostics(&program, output.clone(), sampling_rate, inverse)?;
+            let filter = flamegraph::FlamegraphFilter {
+                focus,
+                exclude,
+                max_depth,
+                min_percent,
+                collapse_crates,
+            };
+            // The simulator's profiler cannot filter, so filtered graphs are sampled here instead.
+            let mut sampler = (!filter.is_empty())
+                .then(|| flamegraph::StackSampler::new(program.symbols.as_ref(), sampling_rate));
+            let options = sim::SimulatorOptions {
+                diagnostics: sampler.is_none().then_some(diagnostics),
+                console_output,
+                machine,
+                ..Default::default()
+            };
+            let mut watcher =
+                watch::Watcher::new(watchpoints, program.symbols.as_ref(), watch_stop);
+            let watching = !watcher.is_empty();
+            let mut chart = flame_chart.map(|path| {
+                flame_chart::FlameChart::new(path, program.symbols.as_ref(), sampling_rate)
+            });
+            let mut observers: Vec<&mut dyn sim::StepObserver> = Vec::new();
+            if watching {
+                observers.push(&mut watcher);
+            }
+            if let Some(chart) = &mut chart {
+                observers.push(chart);
+            }
+            if let Some(sampler) = &mut sampler {
+                observers.push(sampler);
+            }
             tracing::info!("Running simulator with profiler");
             let outcome =
-                sim::run_simulator(&app_bin, input_words, cycle_limit, Some(diagnostics))?;
+                sim::run_simulator(&program, input_words, cycle_limit, options, &mut observers)?;
             sim::report_run_outcome(&outcome);
+            if let Some(sampler) = &sampler {
+                sampler.write_svg(&filter, inverse, &output)?;
+            }
+            if let Some(chart) = chart {
+                chart.write()?;
+            }
+            if watching {
+                watcher.report();
+            }
         }
         cli::Commands::RunTranspiler {
             app_bin,
             input,
             cycles,
             text_path,
+            console_output,
+            oracle_command,
+            transcript_output,
+            on_input_exhausted,
+            load_memory,
         } => {
-            let input_words = input::parse_input_words(&input)?;
+            let input_words = match &input {
+                Some(input) => input::parse_input_words(input)?,
+                None => Vec::new(),
+            };
             let cycle_limit = cycles.unwrap_or(sim::DEFAULT_CYCLES);
+            let program = program::Program::load(&app_bin, text_path.as_deref())?;
             tracing::info!("Running transpiler JIT");
-            let outcome = sim_transpiler::run_transpiler(
-                &app_bin,
-                input_words,
-                cycle_limit,
-                text_path.as_ref(),
-            )?;
+            let options = sim_transpiler::TranspilerOptions {
+                console_output,
+                oracle_command,
+                on_input_exhausted,
+                preload: preload::load(&load_memory)?,
+                machine: sim_transpiler::JIT_MACHINE,
+            };
+            let outcome =
+                sim_transpiler::run_transpiler(&program, input_words, cycle_limit, options)?;
             sim::report_run_outcome(&outcome);
+            if let Some(path) = transcript_output {
+                transcript::write(&outcome.oracle_reads, &path)?;
+            }
         }
         cli::Commands::Prove {
             app_bin,
             input,
+            oracle_transcript,
+            oracle_command,
+            transcript_output,
+            load_memory,
             output,
             backend,
             threads,
             cycles,
             ram_bound,
             level,
+            machine,
+            no_preflight,
         } => {
-            let input_words = input::parse_input_words(&input)?;
             let prover_level = match level {
                 cli::ProverLevel::Base => UnrolledProverLevel::Base,
                 cli::ProverLevel::RecursionUnrolled => UnrolledProverLevel::RecursionUnrolled,
                 cli::ProverLevel::RecursionUnified => UnrolledProverLevel::RecursionUnified,
             };
-            prover::prove(
-                &app_bin,
-                input_words,
-                &output,
+            if !load_memory.is_empty() {
+                tracing::warn!(
+                    "--load-memory is not provable and is ignored; the proof only covers input read through the oracle"
+                );
+            }
+            let program = program::Program::load(&app_bin, None)?;
+            check::check_program(&program, machine)?;
+            let (input_words, recorded_cycles) = match (&input, &oracle_transcript, &oracle_command)
+            {
+                (_, Some(path), _) => (transcript::load_words(path)?, None),
+                (_, _, Some(command)) => {
+                    let transcript_output =
+                        transcript_output.unwrap_or_else(|| output.with_extension("oracle.txt"));
+                    let (words, recorded_cycles) =
+                        prover::record_oracle(
+                        &program,
+                        command,
+                        &transcript_output,
+                        cycles,
+                        machine,
+                    )?;
+                    (words, Some(recorded_cycles))
+                }
+                (Some(input), None, None) => (input::parse_input_words(input)?, None),
+                (None, None, None) => unreachable!("clap requires an input source"),
+            };
+            let options = prover::ProverOptions {
                 backend,
-                threads,
+                worker_threads: threads,
                 cycles,
                 ram_bound,
-                prover_level,
-            )?;
+                level: prover_level,
+                machine,
+                preflight: !no_preflight,
+                recorded_cycles,
+            };
+            prover::prove(&program, input_words, &output, options)?;
         }
         cli::Commands::GenerateVk {
             app_bin,
             output,
             level,
+            machine,
         } => {
             let prover_level = match level {
                 cli::ProverLevel::Base => UnrolledProverLevel::Base,
                 cli::ProverLevel::RecursionUnrolled => UnrolledProverLevel::RecursionUnrolled,
                 cli::ProverLevel::RecursionUnified => UnrolledProverLevel::RecursionUnified,
             };
-            vk::generate_vk(&app_bin, &output, prover_level)?;
+            let program = program::Program::load(&app_bin, None)?;
+            check::check_program(&program, machine)?;
+            vk::generate_vk(&program, &output, prover_level, machine)?;
         }
-        cli::Commands::VerifyProof { proof, vk, level } => {
+        cli::Commands::Check {
+            app_bin,
+            text_path,
+            machine,
+        } => {
+            let program = program::Program::load(&app_bin, text_path.as_deref())?;
+            check::check_program(&program, machine)?;
+        }
+        cli::Commands::Disasm {
+            app_bin,
+            elf,
+            text_path,
+            range,
+            machine,
+        } => {
+            let mut program = program::Program::load(&app_bin, text_path.as_deref())?;
+            if let Some(elf) = elf {
+                program.set_elf_path(elf)?;
+            }
+            disasm::disassemble(&program, machine, range.as_deref())?;
+        }
+        cli::Commands::Size {
+            app_bin,
+            elf,
+            crates,
+            top,
+            json,
+        } => {
+            let mut program = program::Program::load(&app_bin, None)?;
+            if let Some(elf) = elf {
+                program.set_elf_path(elf)?;
+            }
+            size::report(&program, crates, top, json.as_deref())?;
+        }
+        cli::Commands::Pack { elf, out_dir, name } => {
+            let program = program::Program::load(&elf, None)?;
+            pack::pack(&program, &out_dir, name)?;
+        }
+        cli::Commands::BenchRecord {
+            cases,
+            output,
+            cycles,
+        } => {
+            bench::record(&cases, &output, cycles.unwrap_or(sim::DEFAULT_CYCLES))?;
+        }
+        cli::Commands::BenchCompare {
+            baseline,
+            threshold,
+            cycles,
+        } => {
+            bench::compare(&baseline, threshold, cycles.unwrap_or(sim::DEFAULT_CYCLES))?;
+        }
+        cli::Commands::VerifyProof {
+            proof,
+            vk,
+            level,
+            machine,
+        } => {
             let prover_level = match level {
                 cli::ProverLevel::Base => UnrolledProverLevel::Base,
                 cli::ProverLevel::RecursionUnrolled => UnrolledProverLevel::RecursionUnrolled,
                 cli::ProverLevel::RecursionUnified => UnrolledProverLevel::RecursionUnified,
             };
-            vk::verify_proof(&proof, &vk, prover_level)?;
+            vk::verify_proof(&proof, &vk, prover_level, machine)?;
         }
     }
 
@@ -0,0 +1,220 @@
+//! Guest-defined cycle markers.
+//!
+//! A guest emits a marker by writing the following words to the non-determinism CSR:
+//! [`MARKER_MAGIC`], its complement, a header `(kind << 16) | name_len` (kind `0` opens a region,
+//! `1` closes it), and the UTF-8 name packed little-endian into `ceil(name_len / 4)` words.
+//! The complement and the header checks keep ordinary data that happens to equal the magic from
+//! being taken for a marker.
+
+use std::collections::HashMap;
+
+use crate::oracle::FrameStep;
+
+pub const MARKER_MAGIC: u32 = u32::from_le_bytes(*b"MARK");
+
+/// Longest marker name accepted, in bytes.
+pub const MAX_MARKER_NAME: usize = 256;
+
+const KIND_START: u32 = 0;
+const KIND_END: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkerKind {
+    Start,
+    End,
+}
+
+#[derive(Debug, Clone)]
+pub struct MarkerEvent {
+    /// Cycle at which the marker was emitted; `None` when the runner cannot attribute cycles.
+    pub cycle: Option<usize>,
+    pub kind: MarkerKind,
+    pub name: String,
+}
+
+/// Decodes the words following [`MARKER_MAGIC`].
+#[derive(Debug, Default)]
+pub struct MarkerDecoder {
+    checked: bool,
+    kind: Option<MarkerKind>,
+    name_len: usize,
+    name: Vec<u8>,
+}
+
+impl MarkerDecoder {
+    /// Consumes the next word of a marker frame; yields the marker once the frame is complete.
+    pub fn push(&mut self, word: u32) -> FrameStep<(MarkerKind, String)> {
+        if !self.checked {
+            if word != !MARKER_MAGIC {
+                return FrameStep::Invalid;
+            }
+            self.checked = true;
+            return FrameStep::Pending;
+        }
+
+        if self.kind.is_none() {
+            let kind = match word >> 16 {
+                KIND_START => MarkerKind::Start,
+                KIND_END => MarkerKind::End,
+                _ => return FrameStep::Invalid,
+            };
+            let name_len = (word & 0xffff) as usize;
+            if name_len == 0 || name_len > MAX_MARKER_NAME {
+                return FrameStep::Invalid;
+            }
+            self.kind = Some(kind);
+            self.name_len = name_len;
+            return FrameStep::Pending;
+        }
+
+        let remaining = self.name_len - self.name.len();
+        self.name
+            .extend_from_slice(&word.to_le_bytes()[..remaining.min(4)]);
+        if self.name.len() < self.name_len {
+            return FrameStep::Pending;
+        }
+        let name = String::from_utf8_lossy(&self.name).into_owned();
+        match self.kind.take() {
+            Some(kind) => FrameStep::Complete((kind, name)),
+            None => FrameStep::Invalid,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RegionStats {
+    path: Vec<String>,
+    count: usize,
+    cycles: usize,
+}
+
+/// Logs the time spent between matching markers, grouped by their nesting path.
+pub fn report_regions(events: &[MarkerEvent], cycles_executed: usize) {
+    let timed = events.iter().all(|event| event.cycle.is_some());
+    let mut regions: Vec<RegionStats> = Vec::new();
+    let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut stack: Vec<(String, usize)> = Vec::new();
+
+    let mut close = |stack: &mut Vec<(String, usize)>, end_cycle: usize| {
+        let path: Vec<String> = stack.iter().map(|(name, _)| name.clone()).collect();
+        let (_, start_cycle) = stack.pop().expect("stack is not empty");
+        let idx = *index.entry(path.clone()).or_insert_with(|| {
+            regions.push(RegionStats {
+                path,
+                count: 0,
+                cycles: 0,
+            });
+            regions.len() - 1
+        });
+        regions[idx].count += 1;
+        regions[idx].cycles += end_cycle.saturating_sub(start_cycle);
+    };
+
+    for event in events {
+        let cycle = event.cycle.unwrap_or(0);
+        match event.kind {
+            MarkerKind::Start => stack.push((event.name.clone(), cycle)),
+            MarkerKind::End => {
+                let Some(depth) = stack.iter().rposition(|(name, _)| *name == event.name) else {
+                    tracing::warn!("Marker end `{}` has no matching start", event.name);
+                    continue;
+                };
+                while stack.len() > depth + 1 {
+                    tracing::warn!(
+                        "Marker `{}` closed implicitly by end of `{}`",
+                        stack.last().expect("stack is not empty").0,
+                        event.name
+                    );
+                    close(&mut stack, cycle);
+                }
+                close(&mut stack, cycle);
+            }
+        }
+    }
+    while let Some((name, _)) = stack.last() {
+        tracing::warn!("Marker `{name}` was never closed; closing at end of run");
+        close(&mut stack, cycles_executed);
+    }
+
+    // Sorting by path places every region right after its parent.
+    regions.sort_by(|a, b| a.path.cmp(&b.path));
+    tracing::info!("Marker regions:");
+    for region in &regions {
+        let indent = "  ".repeat(region.path.len());
+        let name = region.path.last().expect("path is not empty");
+        if timed {
+            let share = if cycles_executed == 0 {
+                0.0
+            } else {
+                region.cycles as f64 / cycles_executed as f64 * 100.0
+            };
+            tracing::info!(
+                "{indent}{name}: count={}, cycles={} ({share:.2}%), avg={}",
+                region.count,
+                region.cycles,
+                region.cycles / region.count
+            );
+        } else {
+            tracing::info!("{indent}{name}: count={}", region.count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(words: &[u32]) -> Vec<FrameStep<(MarkerKind, String)>> {
+        let mut decoder = MarkerDecoder::default();
+        words.iter().map(|&word| decoder.push(word)).collect()
+    }
+
+    #[test]
+    fn decodes_start_and_end_markers() {
+        let steps = decode(&[
+            !MARKER_MAGIC,
+            5,
+            u32::from_le_bytes(*b"hash"),
+            u32::from_le_bytes(*b"2\0\0\0"),
+        ]);
+        assert_eq!(
+            steps.last(),
+            Some(&FrameStep::Complete((
+                MarkerKind::Start,
+                "hash2".to_string()
+            )))
+        );
+        assert!(steps[..3].iter().all(|step| *step == FrameStep::Pending));
+
+        let steps = decode(&[
+            !MARKER_MAGIC,
+            (KIND_END << 16) | 4,
+            u32::from_le_bytes(*b"loop"),
+        ]);
+        assert_eq!(
+            steps.last(),
+            Some(&FrameStep::Complete((MarkerKind::End, "loop".to_string())))
+        );
+    }
+
+    #[test]
+    fn rejects_a_missing_complement() {
+        assert_eq!(decode(&[MARKER_MAGIC]), [FrameStep::Invalid]);
+        assert_eq!(decode(&[0]), [FrameStep::Invalid]);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        // Unknown kind.
+        assert_eq!(
+            decode(&[!MARKER_MAGIC, (2 << 16) | 4])[1],
+            FrameStep::Invalid
+        );
+        // Empty and overlong names.
+        assert_eq!(decode(&[!MARKER_MAGIC, 0])[1], FrameStep::Invalid);
+        assert_eq!(
+            decode(&[!MARKER_MAGIC, MAX_MARKER_NAME as u32 + 1])[1],
+            FrameStep::Invalid
+        );
+    }
+}
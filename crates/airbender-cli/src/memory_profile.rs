@@ -0,0 +1,265 @@
+//! Stack depth, heap high-water mark and per-page access counts of a simulator run.
+//!
+//! The stack is assumed to grow down towards the heap, as laid out by the guest linker script:
+//! accesses between the heap start and the current stack pointer count as heap accesses.
+
+use std::collections::HashMap;
+
+use crate::elf::SymbolTable;
+use crate::riscv::{self, Instruction};
+use crate::sim::{Step, StepAction, StepObserver};
+
+pub const PAGE_SIZE: u32 = 4096;
+
+/// Default distance between stack and heap below which a warning is logged.
+pub const DEFAULT_STACK_HEAP_MARGIN: u32 = 4096;
+
+/// Number of pages listed in the report.
+const TOP_PAGES: usize = 10;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct PageAccesses {
+    loads: u64,
+    stores: u64,
+}
+
+pub struct MemoryProfiler {
+    stack_top: Option<u32>,
+    heap_start: Option<u32>,
+    margin: u32,
+    /// Lowest stack pointer seen, with its cycle and PC.
+    lowest_sp: Option<(u32, usize, u32)>,
+    /// One past the highest heap byte accessed.
+    heap_end: Option<u32>,
+    pages: HashMap<u32, PageAccesses>,
+    /// Cycle and distance at which the stack first came within `margin` of the heap.
+    collision: Option<(usize, u32)>,
+}
+
+impl MemoryProfiler {
+    pub fn new(symbols: Option<&SymbolTable>, margin: u32) -> Self {
+        let stack_top = symbols.and_then(SymbolTable::stack_top);
+        let heap_start = symbols.and_then(SymbolTable::heap_start);
+        if heap_start.is_none() {
+            tracing::warn!("No heap start symbol in the ELF; heap usage is not tracked");
+        }
+        Self {
+            stack_top,
+            heap_start,
+            margin,
+            lowest_sp: None,
+            heap_end: None,
+            pages: HashMap::new(),
+            collision: None,
+        }
+    }
+
+    fn check_margin(&mut self, step: &Step<'_>) {
+        let (Some((lowest_sp, _, _)), Some(heap_end)) = (self.lowest_sp, self.heap_end) else {
+            return;
+        };
+        if self.collision.is_some() {
+            return;
+        }
+        let distance = lowest_sp.saturating_sub(heap_end);
+        if distance < self.margin {
+            tracing::warn!(
+                "Stack came within {distance} bytes of the heap at cycle {} (sp=0x{lowest_sp:08x}, heap end=0x{heap_end:08x}, pc=0x{:08x})",
+                step.cycle,
+                step.pc
+            );
+            self.collision = Some((step.cycle, distance));
+        }
+    }
+
+    pub fn report(&self) {
+        tracing::info!("Memory profile:");
+        match (self.stack_top, self.lowest_sp) {
+            (_, None) => tracing::info!("  stack: sp was never set"),
+            (Some(top), Some((sp, cycle, pc))) => tracing::info!(
+                "  stack: lowest sp 0x{sp:08x} at cycle {cycle} (pc 0x{pc:08x}), {} bytes below the top",
+                top.saturating_sub(sp)
+            ),
+            (None, Some((sp, cycle, pc))) => {
+                tracing::info!("  stack: lowest sp 0x{sp:08x} at cycle {cycle} (pc 0x{pc:08x})")
+            }
+        }
+        match (self.heap_start, self.heap_end) {
+            (None, _) => {}
+            (Some(start), None) => tracing::info!("  heap: starts at 0x{start:08x}, never touched"),
+            (Some(start), Some(end)) => tracing::info!(
+                "  heap: highest address 0x{:08x}, {} bytes used from 0x{start:08x}",
+                end - 1,
+                end - start
+            ),
+        }
+        if let (Some((sp, _, _)), Some(end)) = (self.lowest_sp, self.heap_end) {
+            tracing::info!(
+                "  closest stack/heap distance: {} bytes",
+                sp.saturating_sub(end)
+            );
+        }
+        if let Some((cycle, distance)) = self.collision {
+            tracing::warn!(
+                "  stack came within {distance} bytes of the heap at cycle {cycle} (margin {} bytes)",
+                self.margin
+            );
+        }
+
+        let mut pages: Vec<(u32, PageAccesses)> = self
+            .pages
+            .iter()
+            .map(|(page, count)| (*page, *count))
+            .collect();
+        pages.sort_by_key(|(page, count)| (std::cmp::Reverse(count.loads + count.stores), *page));
+        tracing::info!(
+            "  {} pages of {PAGE_SIZE} bytes touched; busiest:",
+            pages.len()
+        );
+        tracing::info!("  {:<12} {:>14} {:>14}", "Page", "Loads", "Stores");
+        for (page, count) in pages.iter().take(TOP_PAGES) {
+            tracing::info!(
+                "  0x{:08x} {:>14} {:>14}",
+                page * PAGE_SIZE,
+                count.loads,
+                count.stores
+            );
+        }
+    }
+}
+
+impl StepObserver for MemoryProfiler {
+    fn on_step(&mut self, step: &Step<'_>) -> StepAction {
+        let sp = step.registers[riscv::SP as usize];
+        // Before the startup code sets it, sp is zero.
+        if sp != 0 && self.lowest_sp.is_none_or(|(lowest, _, _)| sp < lowest) {
+            self.lowest_sp = Some((sp, step.cycle, step.pc));
+            self.check_margin(step);
+        }
+
+        let Some(instruction) = riscv::decode(step.instruction) else {
+            return StepAction::Continue;
+        };
+        let Some((address, width)) = instruction.memory_access(step.registers) else {
+            return StepAction::Continue;
+        };
+        let count = self.pages.entry(address / PAGE_SIZE).or_default();
+        if matches!(instruction, Instruction::Store { .. }) {
+            count.stores += 1;
+        } else {
+            count.loads += 1;
+        }
+
+        if let Some(heap_start) = self.heap_start
+            && address >= heap_start
+            && address < sp
+        {
+            let end = address.saturating_add(width);
+            if self.heap_end.is_none_or(|heap_end| end > heap_end) {
+                self.heap_end = Some(end);
+                self.check_margin(step);
+            }
+        }
+        StepAction::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::tests::symbol_table;
+
+    const NOP: u32 = 0x0000_0013;
+    /// `sw t0, 0(t1)`
+    const STORE: u32 = 0x0053_2023;
+    /// `lw t0, 0(t1)`
+    const LOAD: u32 = 0x0003_2283;
+
+    struct Run<'a> {
+        profiler: MemoryProfiler,
+        registers: [u32; 32],
+        cycle: usize,
+        memory: &'a [u32],
+    }
+
+    impl Run<'_> {
+        /// Executes `instruction` with the given `sp` and `t1`.
+        fn step(&mut self, instruction: u32, sp: u32, t1: u32) {
+            self.registers[riscv::SP as usize] = sp;
+            self.registers[6] = t1;
+            self.profiler.on_step(&Step {
+                cycle: self.cycle,
+                pc: 0x100 + 4 * self.cycle as u32,
+                instruction,
+                registers: &self.registers,
+                memory: self.memory,
+            });
+            self.cycle += 1;
+        }
+    }
+
+    fn run(margin: u32) -> Run<'static> {
+        let symbols = symbol_table(&[], &[("_stack_top", 0x1_0000), ("_heap_start", 0x8000)]);
+        Run {
+            profiler: MemoryProfiler::new(Some(&symbols), margin),
+            registers: [0; 32],
+            cycle: 0,
+            memory: &[],
+        }
+    }
+
+    #[test]
+    fn tracks_lowest_sp_and_heap_end() {
+        let mut run = run(16);
+        run.step(NOP, 0, 0);
+        assert!(run.profiler.lowest_sp.is_none());
+        run.step(NOP, 0xff00, 0);
+        run.step(NOP, 0xff80, 0);
+        assert_eq!(run.profiler.lowest_sp, Some((0xff00, 1, 0x104)));
+        assert_eq!(run.profiler.stack_top, Some(0x1_0000));
+
+        run.step(STORE, 0xff00, 0x8100);
+        run.step(LOAD, 0xff00, 0x8040);
+        // Below the heap, and on the stack above sp: neither is a heap access.
+        run.step(LOAD, 0xff00, 0x20);
+        run.step(STORE, 0xff00, 0xff10);
+        assert_eq!(run.profiler.heap_end, Some(0x8104));
+
+        let page = |address: u32| run.profiler.pages[&(address / PAGE_SIZE)];
+        assert_eq!((page(0x8000).loads, page(0x8000).stores), (1, 1));
+        assert_eq!((page(0).loads, page(0).stores), (1, 0));
+        assert_eq!((page(0xf000).loads, page(0xf000).stores), (0, 1));
+        assert!(run.profiler.collision.is_none());
+    }
+
+    #[test]
+    fn warns_once_when_the_stack_nears_the_heap() {
+        let mut run = run(0x100);
+        run.step(STORE, 0xff00, 0x8100);
+        run.step(NOP, 0x8204, 0);
+        assert!(run.profiler.collision.is_none());
+        run.step(NOP, 0x8200, 0);
+        assert_eq!(run.profiler.collision, Some((2, 0xfc)));
+        run.step(NOP, 0x8180, 0);
+        assert_eq!(run.profiler.collision, Some((2, 0xfc)));
+        assert_eq!(run.profiler.lowest_sp, Some((0x8180, 3, 0x10c)));
+    }
+
+    #[test]
+    fn skips_heap_tracking_without_a_heap_symbol() {
+        let symbols = symbol_table(&[], &[]);
+        let mut profiler = MemoryProfiler::new(Some(&symbols), 16);
+        let mut registers = [0; 32];
+        registers[riscv::SP as usize] = 0xff00;
+        registers[6] = 0x8100;
+        profiler.on_step(&Step {
+            cycle: 0,
+            pc: 0x100,
+            instruction: STORE,
+            registers: &registers,
+            memory: &[],
+        });
+        assert_eq!(profiler.heap_end, None);
+        assert_eq!(profiler.pages.len(), 1);
+    }
+}
@@ -0,0 +1,223 @@
+use risc_v_simulator::abstractions::memory::MemorySource;
+use risc_v_simulator::abstractions::non_determinism::NonDeterminismCSRSource;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::console::{self, ConsoleDecoder, GuestPanic, PANIC_SCAN_WINDOW, UART_HELLO};
+use crate::markers::{MARKER_MAGIC, MarkerDecoder, MarkerEvent, MarkerKind};
+use crate::transcript::OracleRead;
+
+/// Host-side record of what the guest wrote to the non-determinism CSR.
+pub struct GuestLog {
+    /// Current cycle, kept up to date by runners that can attribute cycles.
+    cycle: Option<AtomicUsize>,
+    markers: Mutex<Vec<MarkerEvent>>,
+    console: Mutex<ConsoleState>,
+    /// Words the guest read from the input oracle.
+    reads: Mutex<Vec<OracleRead>>,
+}
+
+struct ConsoleState {
+    sink: Box<dyn Write + Send>,
+    /// Trailing console output, scanned for a panic message once the run is over.
+    tail: String,
+}
+
+impl GuestLog {
+    pub fn new(console: Box<dyn Write + Send>) -> Self {
+        Self {
+            cycle: None,
+            markers: Mutex::default(),
+            reads: Mutex::default(),
+            console: Mutex::new(ConsoleState {
+                sink: console,
+                tail: String::new(),
+            }),
+        }
+    }
+
+    /// Creates a log that timestamps events with the cycle set via [`GuestLog::set_cycle`].
+    pub fn timed(console: Box<dyn Write + Send>) -> Self {
+        Self {
+            cycle: Some(AtomicUsize::new(0)),
+            ..Self::new(console)
+        }
+    }
+
+    pub fn set_cycle(&self, cycle: usize) {
+        if let Some(current) = &self.cycle {
+            current.store(cycle, Ordering::Relaxed);
+        }
+    }
+
+    pub fn take_markers(&self) -> Vec<MarkerEvent> {
+        std::mem::take(&mut *self.markers.lock().expect("guest log lock poisoned"))
+    }
+
+    pub fn take_reads(&self) -> Vec<OracleRead> {
+        std::mem::take(&mut *self.reads.lock().expect("guest log lock poisoned"))
+    }
+
+    pub fn guest_panic(&self) -> Option<GuestPanic> {
+        console::find_panic(&self.console.lock().expect("guest log lock poisoned").tail)
+    }
+
+    fn record_marker(&self, kind: MarkerKind, name: String) {
+        let cycle = self
+            .cycle
+            .as_ref()
+            .map(|current| current.load(Ordering::Relaxed));
+        self.markers
+            .lock()
+            .expect("guest log lock poisoned")
+            .push(MarkerEvent { cycle, kind, name });
+    }
+
+    fn record_read(&self, value: u32) {
+        let cycle = self
+            .cycle
+            .as_ref()
+            .map(|current| current.load(Ordering::Relaxed));
+        let mut reads = self.reads.lock().expect("guest log lock poisoned");
+        let index = reads.len();
+        reads.push(OracleRead {
+            cycle,
+            index,
+            value,
+        });
+    }
+
+    fn record_console(&self, payload: &[u8]) {
+        let mut console = self.console.lock().expect("guest log lock poisoned");
+        if let Err(err) = console
+            .sink
+            .write_all(payload)
+            .and_then(|()| console.sink.flush())
+        {
+            tracing::warn!("Failed to write guest console output: {err}");
+        }
+
+        console.tail.push_str(&String::from_utf8_lossy(payload));
+        if console.tail.len() > PANIC_SCAN_WINDOW {
+            let mut cut = console.tail.len() - PANIC_SCAN_WINDOW;
+            while !console.tail.is_char_boundary(cut) {
+                cut += 1;
+            }
+            console.tail.drain(..cut);
+        }
+    }
+}
+
+/// Result of feeding one word to a frame decoder.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameStep<T> {
+    Pending,
+    Complete(T),
+    /// The words do not form a valid frame, so the magic was ordinary data.
+    Invalid,
+}
+
+enum FrameDecoder {
+    Marker(MarkerDecoder),
+    Console(ConsoleDecoder),
+}
+
+struct PendingFrame {
+    decoder: FrameDecoder,
+    /// Words after the magic, handled again as ordinary writes if the frame turns out invalid.
+    words: Vec<u32>,
+}
+
+/// Wraps the input oracle and intercepts guest writes that carry host-side commands.
+pub struct GuestOracle<ND> {
+    inner: ND,
+    log: Arc<GuestLog>,
+    pending: Option<PendingFrame>,
+}
+
+impl<ND> GuestOracle<ND> {
+    pub fn new(inner: ND, log: Arc<GuestLog>) -> Self {
+        Self {
+            inner,
+            log,
+            pending: None,
+        }
+    }
+
+    pub fn inner(&self) -> &ND {
+        &self.inner
+    }
+
+    /// Whether the guest is in the middle of writing a console or marker frame.
+    pub fn in_frame(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn start(&mut self, decoder: FrameDecoder) {
+        self.pending = Some(PendingFrame {
+            decoder,
+            words: Vec::new(),
+        });
+    }
+}
+
+impl<M: MemorySource, ND: NonDeterminismCSRSource<M>> NonDeterminismCSRSource<M>
+    for GuestOracle<ND>
+{
+    fn read(&mut self) -> u32 {
+        let value = self.inner.read();
+        self.log.record_read(value);
+        value
+    }
+
+    fn write_with_memory_access(&mut self, memory: &M, value: u32) {
+        let Some(pending) = &mut self.pending else {
+            match value {
+                MARKER_MAGIC => self.start(FrameDecoder::Marker(MarkerDecoder::default())),
+                UART_HELLO => self.start(FrameDecoder::Console(ConsoleDecoder::default())),
+                _ => self.inner.write_with_memory_access(memory, value),
+            }
+            return;
+        };
+
+        pending.words.push(value);
+        let valid = match &mut pending.decoder {
+            FrameDecoder::Marker(decoder) => match decoder.push(value) {
+                FrameStep::Pending => true,
+                FrameStep::Complete((kind, name)) => {
+                    self.pending = None;
+                    self.log.record_marker(kind, name);
+                    true
+                }
+                FrameStep::Invalid => false,
+            },
+            FrameDecoder::Console(decoder) => match decoder.push(value) {
+                FrameStep::Pending => true,
+                FrameStep::Complete(payload) => {
+                    self.pending = None;
+                    self.log.record_console(&payload);
+                    true
+                }
+                FrameStep::Invalid => false,
+            },
+        };
+        if valid {
+            return;
+        }
+
+        // Resynchronize: the magic was ordinary data, so the words after it are handled again.
+        let pending = self.pending.take().expect("frame is pending");
+        match pending.decoder {
+            FrameDecoder::Marker(_) => {
+                self.inner.write_with_memory_access(memory, MARKER_MAGIC);
+            }
+            FrameDecoder::Console(_) => {
+                tracing::warn!("Dropping a malformed guest console frame");
+            }
+        }
+        for word in pending.words {
+            self.write_with_memory_access(memory, word);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{Context, Result};
+use risc_v_simulator::runner::CUSTOM_ENTRY_POINT;
+use riscv_transpiler::common_constants::rom::ROM_BYTE_SIZE;
+use serde::Serialize;
+use sha3::Digest;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::program::Program;
+
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub name: String,
+    pub source: PathBuf,
+    pub entry_point: u32,
+    pub rom_byte_size: usize,
+    /// Keccak256 of the unpadded ROM image, as stored in `app_bin_hash` of VK files generated
+    /// from the same program.
+    pub app_bin_hash: String,
+    pub bin: ArtifactEntry,
+    pub text: ArtifactEntry,
+    pub elf: Option<ArtifactEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArtifactEntry {
+    /// File name relative to the manifest.
+    pub file: PathBuf,
+    pub size: usize,
+    /// Keccak256 of the file as written, including padding.
+    pub keccak256: String,
+}
+
+pub fn pack(program: &Program, out_dir: &Path, name: Option<String>) -> Result<()> {
+    let name = match name {
+        Some(name) => name,
+        None => program
+            .path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .context("cannot derive artifact name from the input path, use --name")?
+            .to_string(),
+    };
+    let artifacts = program.write_artifacts(out_dir, &name)?;
+
+    let manifest = Manifest {
+        name: name.clone(),
+        source: program.path.clone(),
+        entry_point: CUSTOM_ENTRY_POINT,
+        rom_byte_size: ROM_BYTE_SIZE,
+        app_bin_hash: hex(&sha3::Keccak256::digest(&program.bin)),
+        bin: artifact_entry(&artifacts.bin)?,
+        text: artifact_entry(&artifacts.text)?,
+        elf: artifacts.elf.as_deref().map(artifact_entry).transpose()?,
+    };
+    let manifest_path = out_dir.join(format!("{name}.manifest.json"));
+    let encoded = serde_json::to_string_pretty(&manifest)?;
+    fs::write(&manifest_path, encoded)
+        .with_context(|| format!("failed to write manifest to {}", manifest_path.display()))?;
+
+    tracing::info!(
+        "Packed {} ({} bytes of {ROM_BYTE_SIZE} ROM bytes) into {}",
+        program.path.display(),
+        manifest.bin.size,
+        out_dir.display()
+    );
+    Ok(())
+}
+
+fn artifact_entry(path: &Path) -> Result<ArtifactEntry> {
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(ArtifactEntry {
+        file: PathBuf::from(path.file_name().context("artifact path has no file name")?),
+        size: bytes.len(),
+        keccak256: hex(&sha3::Keccak256::digest(&bytes)),
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_artifacts_and_manifest() {
+        let dir = std::env::temp_dir().join(format!("airbender-cli-{}-pack", std::process::id()));
+        let out_dir = dir.join("out");
+        fs::create_dir_all(&dir).unwrap();
+        // Six bytes, so the packed `.bin` is padded.
+        let image = [0x13, 0x05, 0x15, 0x00, 0x6f, 0xf0];
+        let source = dir.join("app.bin");
+        fs::write(&source, image).unwrap();
+        fs::write(dir.join("app.text"), &image[..4]).unwrap();
+
+        let program = Program::load(&source, None).unwrap();
+        pack(&program, &out_dir, Some("guest".to_string())).unwrap();
+        let manifest = fs::read_to_string(out_dir.join("guest.manifest.json"));
+        let packed_bin = fs::read(out_dir.join("guest.bin"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let manifest: serde_json::Value = serde_json::from_str(&manifest.unwrap()).unwrap();
+        let packed_bin = packed_bin.unwrap();
+        assert!(packed_bin.starts_with(&image) && packed_bin.len() > image.len());
+        assert_eq!(manifest["name"], "guest");
+        assert_eq!(manifest["entry_point"], CUSTOM_ENTRY_POINT);
+        assert_eq!(manifest["bin"]["file"], "guest.bin");
+        assert_eq!(manifest["bin"]["size"], packed_bin.len());
+        assert_eq!(
+            manifest["bin"]["keccak256"],
+            hex(&sha3::Keccak256::digest(&packed_bin))
+        );
+        assert_eq!(
+            manifest["app_bin_hash"],
+            hex(&sha3::Keccak256::digest(image))
+        );
+        assert_eq!(manifest["text"]["file"], "guest.text");
+        assert!(manifest["elf"].is_null());
+    }
+}
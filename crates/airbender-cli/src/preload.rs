@@ -0,0 +1,137 @@
+//! Data placed directly into guest RAM before a run (`--load-memory addr=file`).
+//!
+//! Preloaded memory bypasses the input oracle, so the provers never see it: runs that use it
+//! cannot be reproduced by a proof.
+
+use anyhow::{Context, Result, bail};
+use riscv_transpiler::common_constants::rom::ROM_BYTE_SIZE;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::cli::parse_address;
+
+/// A file to load at a RAM address, as given on the command line.
+#[derive(Debug, Clone)]
+pub struct MemoryLoad {
+    pub address: u32,
+    pub path: PathBuf,
+}
+
+pub fn parse_memory_load(value: &str) -> Result<MemoryLoad, String> {
+    let (address, path) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected `<addr>=<file>`, got `{value}`"))?;
+    Ok(MemoryLoad {
+        address: parse_address(address)?,
+        path: PathBuf::from(path),
+    })
+}
+
+/// File contents as little-endian words, ready to be copied into memory.
+#[derive(Debug, Clone)]
+pub struct MemoryRegion {
+    pub address: u32,
+    pub words: Vec<u32>,
+}
+
+impl MemoryRegion {
+    fn end(&self) -> u64 {
+        u64::from(self.address) + self.words.len() as u64 * 4
+    }
+}
+
+/// Reads the files and checks that the regions are word aligned, stay out of ROM and do not
+/// overlap each other.
+pub fn load(loads: &[MemoryLoad]) -> Result<Vec<MemoryRegion>> {
+    let mut regions = Vec::with_capacity(loads.len());
+    for load in loads {
+        if !load.address.is_multiple_of(4) {
+            bail!(
+                "--load-memory address 0x{:08x} is not word aligned",
+                load.address
+            );
+        }
+        if (load.address as usize) < ROM_BYTE_SIZE {
+            bail!(
+                "--load-memory address 0x{:08x} is inside ROM (first 0x{ROM_BYTE_SIZE:x} bytes)",
+                load.address
+            );
+        }
+        let bytes = fs::read(&load.path)
+            .with_context(|| format!("failed to read {}", load.path.display()))?;
+        let words = bytes
+            .chunks(4)
+            .map(|chunk| {
+                let mut word = [0u8; 4];
+                word[..chunk.len()].copy_from_slice(chunk);
+                u32::from_le_bytes(word)
+            })
+            .collect();
+        regions.push(MemoryRegion {
+            address: load.address,
+            words,
+        });
+    }
+
+    let mut sorted: Vec<&MemoryRegion> = regions.iter().collect();
+    sorted.sort_by_key(|region| region.address);
+    for pair in sorted.windows(2) {
+        if pair[0].end() > u64::from(pair[1].address) {
+            bail!(
+                "--load-memory regions at 0x{:08x} and 0x{:08x} overlap",
+                pair[0].address,
+                pair[1].address
+            );
+        }
+    }
+
+    for region in &regions {
+        tracing::warn!(
+            "Preloading {} bytes at 0x{:08x}; this run is not provable",
+            region.words.len() * 4,
+            region.address
+        );
+    }
+    Ok(regions)
+}
+
+/// Copies the regions into a word-addressed memory image.
+pub fn apply(regions: &[MemoryRegion], memory: &mut [u32]) -> Result<()> {
+    for region in regions {
+        let start = (region.address / 4) as usize;
+        let Some(target) = memory.get_mut(start..start + region.words.len()) else {
+            bail!(
+                "--load-memory region at 0x{:08x} ({} bytes) does not fit into guest memory",
+                region.address,
+                region.words.len() * 4
+            );
+        };
+        target.copy_from_slice(&region.words);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_address_and_path() {
+        let load = parse_memory_load("0x2000_0000=data/blob.bin").unwrap();
+        assert_eq!(load.address, 0x2000_0000);
+        assert_eq!(load.path, PathBuf::from("data/blob.bin"));
+    }
+
+    #[test]
+    fn keeps_equals_signs_in_the_path() {
+        let load = parse_memory_load("64=a=b.bin").unwrap();
+        assert_eq!(load.address, 64);
+        assert_eq!(load.path, PathBuf::from("a=b.bin"));
+    }
+
+    #[test]
+    fn rejects_invalid_loads() {
+        assert!(parse_memory_load("0x1000").is_err());
+        assert!(parse_memory_load("ram=blob.bin").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::{Context, Result, bail};
+use execution_utils::setups;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::elf::{self, ELF_MAGIC, SymbolTable};
+
+/// A guest program, loaded either from an ELF or from a `.bin` with sibling `.text`/`.elf` files.
+#[derive(Debug)]
+pub struct Program {
+    /// Path the program was loaded from.
+    pub path: PathBuf,
+    /// ROM image, as produced by `objcopy -O binary`.
+    ///
+    /// Images extracted from an ELF are padded like `setups::read_and_pad_binary` does.
+    pub bin: Vec<u8>,
+    text: Option<Vec<u8>>,
+    /// ELF with symbols, if one is available.
+    pub elf_path: Option<PathBuf>,
+    pub symbols: Option<SymbolTable>,
+    /// Word views of `bin` and `text`, computed on first use and shared by all runs.
+    bin_words: OnceLock<Vec<u32>>,
+    text_words: OnceLock<Vec<u32>>,
+}
+
+/// Files written by [`Program::write_artifacts`].
+#[derive(Debug)]
+pub struct Artifacts {
+    pub bin: PathBuf,
+    pub text: PathBuf,
+    pub elf: Option<PathBuf>,
+}
+
+impl Program {
+    /// Loads `path` as an ELF if it starts with the ELF magic, otherwise as a raw `.bin`.
+    ///
+    /// For raw binaries, the `.text` section is read from `text_path` (or `<app>.text`) and
+    /// symbols from `<app>.elf`, when those files exist.
+    pub fn load(path: &Path, text_path: Option<&Path>) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        if data.starts_with(ELF_MAGIC) {
+            if text_path.is_some() {
+                tracing::warn!("Ignoring text path: the text section is taken from the ELF");
+            }
+            let image = elf::extract_rom_image(&data)
+                .with_context(|| format!("invalid guest ELF {}", path.display()))?;
+            let file = object::File::parse(&*data)
+                .with_context(|| format!("failed to parse ELF {}", path.display()))?;
+            let (bin, _) = setups::pad_binary(image.bin);
+            let (text, _) = setups::pad_binary(image.text);
+            return Ok(Self {
+                path: path.to_path_buf(),
+                bin,
+                text: Some(text),
+                elf_path: Some(path.to_path_buf()),
+                symbols: Some(SymbolTable::from_object(&file)),
+                bin_words: OnceLock::new(),
+                text_words: OnceLock::new(),
+            });
+        }
+
+        let text_path = text_path
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| sibling_path(path, "text"));
+        let text = if text_path.exists() {
+            Some(
+                fs::read(&text_path)
+                    .with_context(|| format!("failed to read {}", text_path.display()))?,
+            )
+        } else {
+            None
+        };
+        let elf_path = sibling_path(path, "elf");
+        let (elf_path, symbols) = if elf_path.exists() {
+            let symbols = SymbolTable::load(&elf_path)?;
+            (Some(elf_path), Some(symbols))
+        } else {
+            (None, None)
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            bin: data,
+            text,
+            elf_path,
+            symbols,
+            bin_words: OnceLock::new(),
+            text_words: OnceLock::new(),
+        })
+    }
+
+    pub fn is_elf(&self) -> bool {
+        self.elf_path.as_deref() == Some(self.path.as_path())
+    }
+
+    /// Uses symbols from `elf_path` instead of the ones found next to the program.
+    pub fn set_elf_path(&mut self, elf_path: PathBuf) -> Result<()> {
+        self.symbols = Some(SymbolTable::load(&elf_path)?);
+        self.elf_path = Some(elf_path);
+        Ok(())
+    }
+
+    pub fn text(&self) -> Result<&[u8]> {
+        match &self.text {
+            Some(text) => Ok(text),
+            None => bail!(
+                "text section not found for {}: pass an ELF or provide {}",
+                self.path.display(),
+                sibling_path(&self.path, "text").display()
+            ),
+        }
+    }
+
+    /// Writes `<name>.bin`, `<name>.text` and, when available, `<name>.elf` into `dir`.
+    ///
+    /// Both images are padded with `setups::pad_binary`, the same rule `read_and_pad_binary`
+    /// applies when the prover loads them back.
+    pub fn write_artifacts(&self, dir: &Path, name: &str) -> Result<Artifacts> {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+
+        let (bin_bytes, _) = setups::pad_binary(self.bin.clone());
+        let bin = dir.join(format!("{name}.bin"));
+        fs::write(&bin, bin_bytes).with_context(|| format!("failed to write {}", bin.display()))?;
+        let (text_bytes, _) = setups::pad_binary(self.text()?.to_vec());
+        let text = dir.join(format!("{name}.text"));
+        fs::write(&text, text_bytes)
+            .with_context(|| format!("failed to write {}", text.display()))?;
+
+        let elf = match &self.elf_path {
+            Some(source) => {
+                let elf = dir.join(format!("{name}.elf"));
+                if fs::canonicalize(source).ok() != fs::canonicalize(&elf).ok() {
+                    fs::copy(source, &elf)
+                        .with_context(|| format!("failed to write {}", elf.display()))?;
+                }
+                Some(elf)
+            }
+            None => None,
+        };
+
+        Ok(Artifacts { bin, text, elf })
+    }
+
+    pub fn bin_words(&self) -> Result<&[u32]> {
+        if let Some(words) = self.bin_words.get() {
+            return Ok(words);
+        }
+        let words = to_words(&self.bin)
+            .with_context(|| format!("invalid binary {}", self.path.display()))?;
+        Ok(self.bin_words.get_or_init(|| words))
+    }
+
+    pub fn text_words(&self) -> Result<&[u32]> {
+        if let Some(words) = self.text_words.get() {
+            return Ok(words);
+        }
+        let words = to_words(self.text()?)
+            .with_context(|| format!("invalid text section of {}", self.path.display()))?;
+        Ok(self.text_words.get_or_init(|| words))
+    }
+}
+
+/// Returns `<path without .bin/.elf>.<extension>`.
+pub fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let mut sibling = path.to_path_buf();
+    sibling.set_extension(extension);
+    sibling
+}
+
+fn to_words(bytes: &[u8]) -> Result<Vec<u32>> {
+    if !bytes.len().is_multiple_of(4) {
+        bail!("length {} is not a multiple of 4", bytes.len());
+    }
+    Ok(bytes
+        .as_chunks::<4>()
+        .0
+        .iter()
+        .map(|chunk| u32::from_le_bytes(*chunk))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::tests::TestElf;
+
+    const TEXT: [u8; 8] = [0x13, 0x05, 0x15, 0x00, 0x6f, 0xf0, 0xdf, 0xff];
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("airbender-cli-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn loads_sibling_text_and_elf() {
+        let dir = test_dir("siblings");
+        let bin = dir.join("app.bin");
+        fs::write(&bin, TEXT).unwrap();
+        fs::write(dir.join("app.text"), &TEXT[..4]).unwrap();
+        fs::write(dir.join("app.elf"), TestElf::guest(&TEXT).build()).unwrap();
+
+        let program = Program::load(&bin, None);
+        let explicit = dir.join("other.text");
+        fs::write(&explicit, &TEXT[4..]).unwrap();
+        let with_text_path = Program::load(&bin, Some(&explicit));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let program = program.unwrap();
+        assert_eq!(program.bin, TEXT);
+        assert_eq!(program.text().unwrap(), &TEXT[..4]);
+        assert_eq!(program.elf_path, Some(dir.join("app.elf")));
+        assert!(program.symbols.is_some());
+        assert!(!program.is_elf());
+        assert_eq!(with_text_path.unwrap().text().unwrap(), &TEXT[4..]);
+    }
+
+    #[test]
+    fn loads_a_bare_bin_without_siblings() {
+        let dir = test_dir("bare");
+        let bin = dir.join("app.bin");
+        fs::write(&bin, TEXT).unwrap();
+        let program = Program::load(&bin, None);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let program = program.unwrap();
+        assert!(program.text().is_err());
+        assert!(program.elf_path.is_none() && program.symbols.is_none());
+    }
+
+    #[test]
+    fn loads_an_elf_directly() {
+        let dir = test_dir("elf");
+        let elf = dir.join("app.elf");
+        fs::write(&elf, TestElf::guest(&TEXT).build()).unwrap();
+        let program = Program::load(&elf, None);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let program = program.unwrap();
+        assert!(program.is_elf());
+        assert!(program.bin.starts_with(&TEXT));
+        assert!(program.text().unwrap().starts_with(&TEXT));
+    }
+}
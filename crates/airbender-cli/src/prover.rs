@@ -1,53 +1,179 @@
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result, bail};
 use execution_utils::setups;
 use execution_utils::unrolled;
 use execution_utils::unrolled_gpu::{UnrolledProver, UnrolledProverLevel};
 use gpu_prover::execution::prover::ExecutionProverConfiguration;
 use risc_v_simulator::abstractions::non_determinism::QuasiUARTSource;
-use risc_v_simulator::cycle::IMStandardIsaConfigWithUnsignedMulDiv;
+use risc_v_simulator::cycle::{
+    IMStandardIsaConfig, IMStandardIsaConfigWithUnsignedMulDiv, MachineConfig,
+};
 use riscv_transpiler::common_constants::rom::ROM_BYTE_SIZE;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
-use crate::cli::ProverBackend;
-use crate::sim_transpiler;
+use crate::cli::{Machine, ProverBackend};
+use crate::host_oracle::OracleCommand;
+use crate::program::Program;
+use crate::sim::{self, SimulationOutcome, SimulatorOptions};
+use crate::sim_transpiler::{self, JIT_MACHINE, TranspilerOptions};
+use crate::transcript;
 
 const DEFAULT_RAM_BOUND_BYTES: usize = 1 << 30;
 const DEFAULT_CPU_CYCLE_BOUND: usize = u32::MAX as usize;
 
+pub struct ProverOptions {
+    pub backend: ProverBackend,
+    /// Worker thread count for the unrolled prover.
+    pub worker_threads: Option<usize>,
+    /// Cycle bound of the preflight run and of CPU proving.
+    pub cycles: Option<usize>,
+    /// RAM bound in bytes for CPU proving.
+    pub ram_bound: Option<usize>,
+    pub level: UnrolledProverLevel,
+    pub machine: Machine,
+    /// Whether to run the guest before proving, on the JIT if it implements `machine`.
+    pub preflight: bool,
+    /// Cycles of the run that recorded the input from a helper process; that run stands in for
+    /// the preflight, since a recording cannot be read past its end.
+    pub recorded_cycles: Option<usize>,
+}
+
 pub fn prove(
-    app_bin_path: &Path,
+    program: &Program,
     input_words: Vec<u32>,
     output: &Path,
-    backend: ProverBackend,
-    worker_threads: Option<usize>,
-    cycles: Option<usize>,
-    ram_bound: Option<usize>,
-    level: UnrolledProverLevel,
+    options: ProverOptions,
 ) -> Result<()> {
-    match backend {
-        ProverBackend::Gpu => prove_gpu(app_bin_path, input_words, output, worker_threads, level),
-        ProverBackend::Cpu => prove_cpu(
-            app_bin_path,
+    if options.backend == ProverBackend::Gpu && options.machine != Machine::UnsignedMulDiv {
+        bail!(
+            "GPU backend only supports {}, got {}",
+            Machine::UnsignedMulDiv,
+            options.machine
+        );
+    }
+    let preflight_cycles = match options.recorded_cycles {
+        Some(cycles) => Some(cycles),
+        None if options.preflight => Some(preflight(
+            program,
+            &input_words,
+            options.cycles,
+            options.machine,
+        )?),
+        None => None,
+    };
+    match (options.backend, options.machine) {
+        (ProverBackend::Gpu, _) => prove_gpu(
+            program,
             input_words,
             output,
-            worker_threads,
-            cycles,
-            ram_bound,
-            level,
+            options.worker_threads,
+            options.level,
         ),
+        (ProverBackend::Cpu, Machine::Standard) => prove_cpu::<IMStandardIsaConfig>(
+            program,
+            input_words,
+            output,
+            &options,
+            preflight_cycles,
+        ),
+        (ProverBackend::Cpu, Machine::UnsignedMulDiv) => {
+            prove_cpu::<IMStandardIsaConfigWithUnsignedMulDiv>(
+                program,
+                input_words,
+                output,
+                &options,
+                preflight_cycles,
+            )
+        }
+    }
+}
+
+/// Runs the program before committing to a proof and returns the cycles it took. Fails if the
+/// guest reads past the end of its input, which the provers would not notice, or does not finish
+/// within `cycles`.
+fn preflight(
+    program: &Program,
+    input_words: &[u32],
+    cycles: Option<usize>,
+    machine: Machine,
+) -> Result<usize> {
+    let outcome = preflight_run(program, input_words.to_vec(), None, cycles, machine)?;
+    if outcome.input_exhausted {
+        bail!(
+            "input is too short: the guest read past the end of its {} words",
+            input_words.len()
+        );
+    }
+    if !outcome.reached_end {
+        bail!(
+            "preflight run did not finish within {} cycles; raise --cycles or pass --no-preflight",
+            outcome.cycles_executed
+        );
+    }
+    Ok(outcome.cycles_executed)
+}
+
+/// Runs the program once with the helper process as its oracle and returns the words it read
+/// and the cycles it took. The provers replay their input, so they are fed this recording
+/// instead of the helper; the transcript makes the proof reproducible with `--oracle-transcript`.
+pub fn record_oracle(
+    program: &Program,
+    command: &OracleCommand,
+    transcript_path: &Path,
+    cycles: Option<usize>,
+    machine: Machine,
+) -> Result<(Vec<u32>, usize)> {
+    tracing::info!("Recording oracle input");
+    let outcome = preflight_run(program, Vec::new(), Some(command), cycles, machine)?;
+    if !outcome.reached_end {
+        bail!(
+            "preflight run did not finish within {} cycles",
+            outcome.cycles_executed
+        );
+    }
+    transcript::write(&outcome.oracle_reads, transcript_path)?;
+    let words = outcome.oracle_reads.iter().map(|read| read.value).collect();
+    Ok((words, outcome.cycles_executed))
+}
+
+/// Runs the program on the JIT, or on the simulator for machines the JIT does not implement.
+fn preflight_run(
+    program: &Program,
+    input_words: Vec<u32>,
+    oracle_command: Option<&OracleCommand>,
+    cycles: Option<usize>,
+    machine: Machine,
+) -> Result<SimulationOutcome> {
+    let cycles = cycles.unwrap_or(DEFAULT_CPU_CYCLE_BOUND);
+    if machine == JIT_MACHINE {
+        tracing::info!("Running transpiler preflight");
+        let options = TranspilerOptions {
+            oracle_command: oracle_command.cloned(),
+            machine,
+            ..Default::default()
+        };
+        sim_transpiler::run_transpiler(program, input_words, cycles, options)
+    } else {
+        tracing::info!("Running simulator preflight; the JIT only implements {JIT_MACHINE}");
+        let options = SimulatorOptions {
+            oracle_command: oracle_command.cloned(),
+            machine,
+            ..Default::default()
+        };
+        sim::run_simulator(program, input_words, cycles, options, &mut [])
     }
 }
 
 fn prove_gpu(
-    app_bin_path: &Path,
+    program: &Program,
     input_words: Vec<u32>,
     output: &Path,
     worker_threads: Option<usize>,
     level: UnrolledProverLevel,
 ) -> Result<()> {
-    let prover = create_unrolled_prover(app_bin_path, worker_threads, level)?;
+    // Kept until the proof is done, in case the prover reads the unpacked files lazily.
+    let (prover, _artifacts) = create_unrolled_prover(program, worker_threads, level)?;
     let oracle = QuasiUARTSource::new_with_reads(input_words);
     tracing::info!("Starting proof generation");
     let start = Instant::now();
@@ -63,64 +189,40 @@ fn prove_gpu(
     Ok(())
 }
 
-fn prove_cpu(
-    app_bin_path: &Path,
+fn prove_cpu<C: MachineConfig>(
+    program: &Program,
     input_words: Vec<u32>,
     output: &Path,
-    worker_threads: Option<usize>,
-    cycles: Option<usize>,
-    ram_bound: Option<usize>,
-    level: UnrolledProverLevel,
+    options: &ProverOptions,
+    preflight_cycles: Option<usize>,
 ) -> Result<()> {
-    if level != UnrolledProverLevel::Base {
+    if options.level != UnrolledProverLevel::Base {
         bail!("CPU backend currently supports only --level base");
     }
 
-    let base_path = strip_bin_suffix(app_bin_path)?;
-    let app_bin_path = PathBuf::from(format!("{base_path}.bin"));
-    let app_text_path = PathBuf::from(format!("{base_path}.text"));
-    if !app_bin_path.exists() {
-        bail!("binary not found: {}", app_bin_path.display());
-    }
-    if !app_text_path.exists() {
-        bail!("text file not found: {}", app_text_path.display());
-    }
+    let (_, binary_u32) = setups::pad_binary(program.bin.clone());
+    let (_, text_u32) = setups::pad_binary(program.text()?.to_vec());
 
-    let (_, binary_u32) = setups::read_and_pad_binary(&app_bin_path);
-    let (_, text_u32) = setups::read_and_pad_binary(&app_text_path);
-
-    let cycles_bound = match cycles {
-        Some(value) => value,
-        None => {
-            tracing::info!("Estimating cycles via transpiler (no --cycles provided)");
-            let outcome = sim_transpiler::run_transpiler(
-                &app_bin_path,
-                input_words.clone(),
-                DEFAULT_CPU_CYCLE_BOUND,
-                Some(&app_text_path),
-            )?;
-            outcome.cycles_executed
-        }
+    // Without --cycles, the bound is the cycle count of the preflight run.
+    let Some(cycles_bound) = options.cycles.or(preflight_cycles) else {
+        bail!("--backend cpu needs --cycles when the preflight is skipped");
     };
 
     if cycles_bound == 0 {
         bail!("cycles bound must be greater than 0");
     }
 
-    let ram_bound = ram_bound.unwrap_or(DEFAULT_RAM_BOUND_BYTES);
+    let ram_bound = options.ram_bound.unwrap_or(DEFAULT_RAM_BOUND_BYTES);
     if ram_bound < ROM_BYTE_SIZE {
-        bail!(
-            "ram-bound must be at least {} bytes",
-            ROM_BYTE_SIZE
-        );
+        bail!("ram-bound must be at least {} bytes", ROM_BYTE_SIZE);
     }
 
-    let threads = worker_threads
+    let threads = options
+        .worker_threads
         .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
         .unwrap_or(1);
-    let worker = execution_utils::prover_examples::prover::worker::Worker::new_with_num_threads(
-        threads,
-    );
+    let worker =
+        execution_utils::prover_examples::prover::worker::Worker::new_with_num_threads(threads);
 
     let oracle = QuasiUARTSource::new_with_reads(input_words);
     tracing::info!(
@@ -129,9 +231,14 @@ fn prove_cpu(
         ram_bound
     );
     let start = Instant::now();
-    let proof = unrolled::prove_unrolled_for_machine_configuration_into_program_proof::<
-        IMStandardIsaConfigWithUnsignedMulDiv,
-    >(&binary_u32, &text_u32, cycles_bound, oracle, ram_bound, &worker);
+    let proof = unrolled::prove_unrolled_for_machine_configuration_into_program_proof::<C>(
+        &binary_u32,
+        &text_u32,
+        cycles_bound,
+        oracle,
+        ram_bound,
+        &worker,
+    );
     let elapsed = start.elapsed().as_secs_f64();
     tracing::info!("Proof generated in {elapsed:.3}s");
     tracing::info!("{}", proof.debug_info());
@@ -143,31 +250,50 @@ fn prove_cpu(
     Ok(())
 }
 
-fn strip_bin_suffix(path: &Path) -> Result<String> {
-    let path_str = path
-        .to_str()
-        .ok_or_else(|| anyhow::anyhow!("app path is not valid UTF-8"))?;
-    if let Some(stripped) = path_str.strip_suffix(".bin") {
-        Ok(stripped.to_string())
-    } else {
-        Ok(path_str.to_string())
-    }
-}
-
 fn create_unrolled_prover(
-    app_bin_path: &Path,
+    program: &Program,
     worker_threads: Option<usize>,
     level: UnrolledProverLevel,
-) -> Result<UnrolledProver> {
-    let base_path = strip_bin_suffix(app_bin_path)?;
+) -> Result<(UnrolledProver, Option<ArtifactDir>)> {
+    let (base_path, artifacts) = prover_base_path(program)?;
     let mut configuration = ExecutionProverConfiguration::default();
     if let Some(threads) = worker_threads {
         configuration.max_thread_pool_threads = Some(threads);
         configuration.replay_worker_threads_count = threads;
     }
-    Ok(UnrolledProver::new(
-        &base_path,
-        configuration,
-        level,
-    ))
+    let prover = UnrolledProver::new(&base_path, configuration, level);
+    Ok((prover, artifacts))
+}
+
+/// Temporary directory with unpacked ELF artifacts, removed when dropped.
+struct ArtifactDir(PathBuf);
+
+impl Drop for ArtifactDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.0)
+            && err.kind() != std::io::ErrorKind::NotFound
+        {
+            tracing::warn!("Failed to remove {}: {err}", self.0.display());
+        }
+    }
+}
+
+/// `UnrolledProver` reads `<base>.bin` and `<base>.text` on its own, so ELF inputs are unpacked
+/// into a temporary directory first.
+fn prover_base_path(program: &Program) -> Result<(String, Option<ArtifactDir>)> {
+    let (path, artifacts) = if program.is_elf() {
+        let dir =
+            ArtifactDir(std::env::temp_dir().join(format!("airbender-cli-{}", std::process::id())));
+        (program.write_artifacts(&dir.0, "app")?.bin, Some(dir))
+    } else {
+        (program.path.clone(), None)
+    };
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("app path is not valid UTF-8"))?;
+    let base_path = path_str
+        .strip_suffix(".bin")
+        .unwrap_or(path_str)
+        .to_string();
+    Ok((base_path, artifacts))
 }
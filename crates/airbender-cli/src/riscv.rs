@@ -0,0 +1,712 @@
+//! RV32IM + Zicsr instruction decoding.
+
+pub const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+pub const RA: u8 = 1;
+pub const SP: u8 = 2;
+pub const GP: u8 = 3;
+
+/// CSR the guest reads input words from and writes host commands to.
+pub const NON_DETERMINISM_CSR: u32 = 0x7c0;
+
+const OPCODE_LOAD: u32 = 0b000_0011;
+const OPCODE_MISC_MEM: u32 = 0b000_1111;
+const OPCODE_OP_IMM: u32 = 0b001_0011;
+const OPCODE_AUIPC: u32 = 0b001_0111;
+const OPCODE_STORE: u32 = 0b010_0011;
+const OPCODE_OP: u32 = 0b011_0011;
+const OPCODE_LUI: u32 = 0b011_0111;
+const OPCODE_BRANCH: u32 = 0b110_0011;
+const OPCODE_JALR: u32 = 0b110_0111;
+const OPCODE_JAL: u32 = 0b110_1111;
+const OPCODE_SYSTEM: u32 = 0b111_0011;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchOp {
+    Beq,
+    Bne,
+    Blt,
+    Bge,
+    Bltu,
+    Bgeu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadOp {
+    Lb,
+    Lh,
+    Lw,
+    Lbu,
+    Lhu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreOp {
+    Sb,
+    Sh,
+    Sw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MulDivOp {
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsrOp {
+    Csrrw,
+    Csrrs,
+    Csrrc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsrSource {
+    Register(u8),
+    Immediate(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Lui {
+        rd: u8,
+        imm: u32,
+    },
+    Auipc {
+        rd: u8,
+        imm: u32,
+    },
+    Jal {
+        rd: u8,
+        offset: i32,
+    },
+    Jalr {
+        rd: u8,
+        rs1: u8,
+        offset: i32,
+    },
+    Branch {
+        op: BranchOp,
+        rs1: u8,
+        rs2: u8,
+        offset: i32,
+    },
+    Load {
+        op: LoadOp,
+        rd: u8,
+        rs1: u8,
+        offset: i32,
+    },
+    Store {
+        op: StoreOp,
+        rs1: u8,
+        rs2: u8,
+        offset: i32,
+    },
+    OpImm {
+        op: AluOp,
+        rd: u8,
+        rs1: u8,
+        imm: i32,
+    },
+    Op {
+        op: AluOp,
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+    },
+    MulDiv {
+        op: MulDivOp,
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+    },
+    Fence,
+    FenceI,
+    Ecall,
+    Ebreak,
+    Mret,
+    Wfi,
+    Csr {
+        op: CsrOp,
+        rd: u8,
+        csr: u32,
+        src: CsrSource,
+    },
+}
+
+impl Instruction {
+    /// Returns the target of a `jal` or conditional branch at `pc`.
+    pub fn jump_target(&self, pc: u32) -> Option<u32> {
+        match *self {
+            Instruction::Jal { offset, .. } | Instruction::Branch { offset, .. } => {
+                Some(pc.wrapping_add_signed(offset))
+            }
+            _ => None,
+        }
+    }
+
+    /// Formats the instruction as assembly, using common pseudo-instructions where they apply.
+    /// Jump targets are printed as absolute addresses, so `pc` is required.
+    pub fn assembly(&self, pc: u32) -> String {
+        let reg = |idx: u8| REGISTER_NAMES[idx as usize];
+        match *self {
+            Instruction::Lui { rd, imm } => format!("lui {}, 0x{:x}", reg(rd), imm >> 12),
+            Instruction::Auipc { rd, imm } => format!("auipc {}, 0x{:x}", reg(rd), imm >> 12),
+            Instruction::Jal { rd: 0, .. } => format!("j 0x{:x}", self.jump_target(pc).unwrap()),
+            Instruction::Jal { rd, .. } if rd == RA => {
+                format!("jal 0x{:x}", self.jump_target(pc).unwrap())
+            }
+            Instruction::Jal { rd, .. } => {
+                format!("jal {}, 0x{:x}", reg(rd), self.jump_target(pc).unwrap())
+            }
+            Instruction::Jalr {
+                rd: 0,
+                rs1: RA,
+                offset: 0,
+            } => "ret".to_string(),
+            Instruction::Jalr { rd, rs1, offset } => {
+                format!("jalr {}, {offset}({})", reg(rd), reg(rs1))
+            }
+            Instruction::Branch { op, rs1, rs2, .. } => {
+                let mnemonic = match op {
+                    BranchOp::Beq => "beq",
+                    BranchOp::Bne => "bne",
+                    BranchOp::Blt => "blt",
+                    BranchOp::Bge => "bge",
+                    BranchOp::Bltu => "bltu",
+                    BranchOp::Bgeu => "bgeu",
+                };
+                let target = self.jump_target(pc).unwrap();
+                format!("{mnemonic} {}, {}, 0x{target:x}", reg(rs1), reg(rs2))
+            }
+            Instruction::Load {
+                op,
+                rd,
+                rs1,
+                offset,
+            } => {
+                let mnemonic = match op {
+                    LoadOp::Lb => "lb",
+                    LoadOp::Lh => "lh",
+                    LoadOp::Lw => "lw",
+                    LoadOp::Lbu => "lbu",
+                    LoadOp::Lhu => "lhu",
+                };
+                format!("{mnemonic} {}, {offset}({})", reg(rd), reg(rs1))
+            }
+            Instruction::Store {
+                op,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let mnemonic = match op {
+                    StoreOp::Sb => "sb",
+                    StoreOp::Sh => "sh",
+                    StoreOp::Sw => "sw",
+                };
+                format!("{mnemonic} {}, {offset}({})", reg(rs2), reg(rs1))
+            }
+            Instruction::OpImm {
+                op: AluOp::Add,
+                rd: 0,
+                rs1: 0,
+                imm: 0,
+            } => "nop".to_string(),
+            Instruction::OpImm {
+                op: AluOp::Add,
+                rd,
+                rs1: 0,
+                imm,
+            } => format!("li {}, {imm}", reg(rd)),
+            Instruction::OpImm {
+                op: AluOp::Add,
+                rd,
+                rs1,
+                imm: 0,
+            } => format!("mv {}, {}", reg(rd), reg(rs1)),
+            Instruction::OpImm { op, rd, rs1, imm } => {
+                let mnemonic = match op {
+                    AluOp::Add => "addi",
+                    AluOp::Slt => "slti",
+                    AluOp::Sltu => "sltiu",
+                    AluOp::Xor => "xori",
+                    AluOp::Or => "ori",
+                    AluOp::And => "andi",
+                    AluOp::Sll => "slli",
+                    AluOp::Srl => "srli",
+                    AluOp::Sra => "srai",
+                    AluOp::Sub => unreachable!("no immediate form of sub"),
+                };
+                format!("{mnemonic} {}, {}, {imm}", reg(rd), reg(rs1))
+            }
+            Instruction::Op { op, rd, rs1, rs2 } => {
+                let mnemonic = match op {
+                    AluOp::Add => "add",
+                    AluOp::Sub => "sub",
+                    AluOp::Sll => "sll",
+                    AluOp::Slt => "slt",
+                    AluOp::Sltu => "sltu",
+                    AluOp::Xor => "xor",
+                    AluOp::Srl => "srl",
+                    AluOp::Sra => "sra",
+                    AluOp::Or => "or",
+                    AluOp::And => "and",
+                };
+                format!("{mnemonic} {}, {}, {}", reg(rd), reg(rs1), reg(rs2))
+            }
+            Instruction::MulDiv { op, rd, rs1, rs2 } => {
+                let mnemonic = match op {
+                    MulDivOp::Mul => "mul",
+                    MulDivOp::Mulh => "mulh",
+                    MulDivOp::Mulhsu => "mulhsu",
+                    MulDivOp::Mulhu => "mulhu",
+                    MulDivOp::Div => "div",
+                    MulDivOp::Divu => "divu",
+                    MulDivOp::Rem => "rem",
+                    MulDivOp::Remu => "remu",
+                };
+                format!("{mnemonic} {}, {}, {}", reg(rd), reg(rs1), reg(rs2))
+            }
+            Instruction::Fence => "fence".to_string(),
+            Instruction::FenceI => "fence.i".to_string(),
+            Instruction::Ecall => "ecall".to_string(),
+            Instruction::Ebreak => "ebreak".to_string(),
+            Instruction::Mret => "mret".to_string(),
+            Instruction::Wfi => "wfi".to_string(),
+            Instruction::Csr { op, rd, csr, src } => {
+                let (mnemonic, operand) = match (op, src) {
+                    (CsrOp::Csrrw, CsrSource::Register(rs1)) => ("csrrw", reg(rs1).to_string()),
+                    (CsrOp::Csrrs, CsrSource::Register(rs1)) => ("csrrs", reg(rs1).to_string()),
+                    (CsrOp::Csrrc, CsrSource::Register(rs1)) => ("csrrc", reg(rs1).to_string()),
+                    (CsrOp::Csrrw, CsrSource::Immediate(imm)) => ("csrrwi", imm.to_string()),
+                    (CsrOp::Csrrs, CsrSource::Immediate(imm)) => ("csrrsi", imm.to_string()),
+                    (CsrOp::Csrrc, CsrSource::Immediate(imm)) => ("csrrci", imm.to_string()),
+                };
+                format!("{mnemonic} {}, 0x{csr:x}, {operand}", reg(rd))
+            }
+        }
+    }
+
+    /// Returns the accessed address and access width in bytes for loads and stores.
+    pub fn memory_access(&self, registers: &[u32; 32]) -> Option<(u32, u32)> {
+        let (rs1, offset, width) = match *self {
+            Instruction::Load {
+                op, rs1, offset, ..
+            } => {
+                let width = match op {
+                    LoadOp::Lb | LoadOp::Lbu => 1,
+                    LoadOp::Lh | LoadOp::Lhu => 2,
+                    LoadOp::Lw => 4,
+                };
+                (rs1, offset, width)
+            }
+            Instruction::Store {
+                op, rs1, offset, ..
+            } => {
+                let width = match op {
+                    StoreOp::Sb => 1,
+                    StoreOp::Sh => 2,
+                    StoreOp::Sw => 4,
+                };
+                (rs1, offset, width)
+            }
+            _ => return None,
+        };
+        let address = registers[rs1 as usize].wrapping_add_signed(offset);
+        Some((address, width))
+    }
+}
+
+/// Returns the CSR number if `instruction` is a Zicsr instruction (`csrrw`, `csrrs`, ...).
+pub fn csr_access(instruction: u32) -> Option<u32> {
+    match decode(instruction)? {
+        Instruction::Csr { csr, .. } => Some(csr),
+        _ => None,
+    }
+}
+
+/// Whether `instruction` reads a word from the input oracle.
+pub fn reads_oracle(instruction: u32) -> bool {
+    matches!(
+        decode(instruction),
+        Some(Instruction::Csr { rd, csr: NON_DETERMINISM_CSR, .. }) if rd != 0
+    )
+}
+
+/// Decodes a 32-bit instruction; returns `None` for encodings outside RV32IM + Zicsr.
+pub fn decode(word: u32) -> Option<Instruction> {
+    let opcode = word & 0x7f;
+    let rd = ((word >> 7) & 0x1f) as u8;
+    let funct3 = (word >> 12) & 0x7;
+    let rs1 = ((word >> 15) & 0x1f) as u8;
+    let rs2 = ((word >> 20) & 0x1f) as u8;
+    let funct7 = word >> 25;
+
+    let instruction = match opcode {
+        OPCODE_LUI => Instruction::Lui {
+            rd,
+            imm: word & 0xffff_f000,
+        },
+        OPCODE_AUIPC => Instruction::Auipc {
+            rd,
+            imm: word & 0xffff_f000,
+        },
+        OPCODE_JAL => Instruction::Jal {
+            rd,
+            offset: j_immediate(word),
+        },
+        OPCODE_JALR if funct3 == 0 => Instruction::Jalr {
+            rd,
+            rs1,
+            offset: i_immediate(word),
+        },
+        OPCODE_BRANCH => {
+            let op = match funct3 {
+                0b000 => BranchOp::Beq,
+                0b001 => BranchOp::Bne,
+                0b100 => BranchOp::Blt,
+                0b101 => BranchOp::Bge,
+                0b110 => BranchOp::Bltu,
+                0b111 => BranchOp::Bgeu,
+                _ => return None,
+            };
+            Instruction::Branch {
+                op,
+                rs1,
+                rs2,
+                offset: b_immediate(word),
+            }
+        }
+        OPCODE_LOAD => {
+            let op = match funct3 {
+                0b000 => LoadOp::Lb,
+                0b001 => LoadOp::Lh,
+                0b010 => LoadOp::Lw,
+                0b100 => LoadOp::Lbu,
+                0b101 => LoadOp::Lhu,
+                _ => return None,
+            };
+            Instruction::Load {
+                op,
+                rd,
+                rs1,
+                offset: i_immediate(word),
+            }
+        }
+        OPCODE_STORE => {
+            let op = match funct3 {
+                0b000 => StoreOp::Sb,
+                0b001 => StoreOp::Sh,
+                0b010 => StoreOp::Sw,
+                _ => return None,
+            };
+            Instruction::Store {
+                op,
+                rs1,
+                rs2,
+                offset: s_immediate(word),
+            }
+        }
+        OPCODE_OP_IMM => {
+            let imm = i_immediate(word);
+            let op = match (funct3, funct7) {
+                (0b000, _) => AluOp::Add,
+                (0b010, _) => AluOp::Slt,
+                (0b011, _) => AluOp::Sltu,
+                (0b100, _) => AluOp::Xor,
+                (0b110, _) => AluOp::Or,
+                (0b111, _) => AluOp::And,
+                (0b001, 0b000_0000) => AluOp::Sll,
+                (0b101, 0b000_0000) => AluOp::Srl,
+                (0b101, 0b010_0000) => AluOp::Sra,
+                _ => return None,
+            };
+            let imm = match op {
+                AluOp::Sll | AluOp::Srl | AluOp::Sra => imm & 0x1f,
+                _ => imm,
+            };
+            Instruction::OpImm { op, rd, rs1, imm }
+        }
+        OPCODE_OP if funct7 == 0b000_0001 => {
+            let op = match funct3 {
+                0b000 => MulDivOp::Mul,
+                0b001 => MulDivOp::Mulh,
+                0b010 => MulDivOp::Mulhsu,
+                0b011 => MulDivOp::Mulhu,
+                0b100 => MulDivOp::Div,
+                0b101 => MulDivOp::Divu,
+                0b110 => MulDivOp::Rem,
+                _ => MulDivOp::Remu,
+            };
+            Instruction::MulDiv { op, rd, rs1, rs2 }
+        }
+        OPCODE_OP => {
+            let op = match (funct3, funct7) {
+                (0b000, 0b000_0000) => AluOp::Add,
+                (0b000, 0b010_0000) => AluOp::Sub,
+                (0b001, 0b000_0000) => AluOp::Sll,
+                (0b010, 0b000_0000) => AluOp::Slt,
+                (0b011, 0b000_0000) => AluOp::Sltu,
+                (0b100, 0b000_0000) => AluOp::Xor,
+                (0b101, 0b000_0000) => AluOp::Srl,
+                (0b101, 0b010_0000) => AluOp::Sra,
+                (0b110, 0b000_0000) => AluOp::Or,
+                (0b111, 0b000_0000) => AluOp::And,
+                _ => return None,
+            };
+            Instruction::Op { op, rd, rs1, rs2 }
+        }
+        OPCODE_MISC_MEM if funct3 == 0b000 => Instruction::Fence,
+        OPCODE_MISC_MEM if funct3 == 0b001 => Instruction::FenceI,
+        OPCODE_SYSTEM => match funct3 {
+            0b000 => match word {
+                0x0000_0073 => Instruction::Ecall,
+                0x0010_0073 => Instruction::Ebreak,
+                0x3020_0073 => Instruction::Mret,
+                0x1050_0073 => Instruction::Wfi,
+                _ => return None,
+            },
+            0b100 => return None,
+            _ => {
+                let op = match funct3 & 0b011 {
+                    0b01 => CsrOp::Csrrw,
+                    0b10 => CsrOp::Csrrs,
+                    _ => CsrOp::Csrrc,
+                };
+                let src = if funct3 & 0b100 == 0 {
+                    CsrSource::Register(rs1)
+                } else {
+                    CsrSource::Immediate(rs1 as u32)
+                };
+                Instruction::Csr {
+                    op,
+                    rd,
+                    csr: word >> 20,
+                    src,
+                }
+            }
+        },
+        _ => return None,
+    };
+    Some(instruction)
+}
+
+fn i_immediate(word: u32) -> i32 {
+    (word as i32) >> 20
+}
+
+fn s_immediate(word: u32) -> i32 {
+    (((word & 0xfe00_0000) as i32) >> 20) | ((word >> 7) & 0x1f) as i32
+}
+
+fn b_immediate(word: u32) -> i32 {
+    (((word & 0x8000_0000) as i32) >> 19)
+        | (((word >> 7) & 0x1) << 11) as i32
+        | (((word >> 25) & 0x3f) << 5) as i32
+        | (((word >> 8) & 0xf) << 1) as i32
+}
+
+fn j_immediate(word: u32) -> i32 {
+    (((word & 0x8000_0000) as i32) >> 11)
+        | (word & 0x000f_f000) as i32
+        | (((word >> 20) & 0x1) << 11) as i32
+        | (((word >> 21) & 0x3ff) << 1) as i32
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// `jalr x0, 0(ra)`
+    pub(crate) const RET: u32 = 0x0000_8067;
+
+    /// `jal ra, offset`
+    pub(crate) fn jal_ra(offset: i32) -> u32 {
+        let imm = offset as u32;
+        ((imm >> 20 & 1) << 31)
+            | ((imm >> 1 & 0x3ff) << 21)
+            | ((imm >> 11 & 1) << 20)
+            | ((imm >> 12 & 0xff) << 12)
+            | (u32::from(RA) << 7)
+            | 0x6f
+    }
+
+    #[test]
+    fn decodes_one_encoding_per_format() {
+        // lui a0, 0x12345
+        assert_eq!(
+            decode(0x1234_5537),
+            Some(Instruction::Lui {
+                rd: 10,
+                imm: 0x1234_5000
+            })
+        );
+        // auipc ra, 0
+        assert_eq!(
+            decode(0x0000_0097),
+            Some(Instruction::Auipc { rd: 1, imm: 0 })
+        );
+        // lw a0, 8(sp)
+        assert_eq!(
+            decode(0x0081_2503),
+            Some(Instruction::Load {
+                op: LoadOp::Lw,
+                rd: 10,
+                rs1: 2,
+                offset: 8
+            })
+        );
+        // sub a0, a1, a2
+        assert_eq!(
+            decode(0x40c5_8533),
+            Some(Instruction::Op {
+                op: AluOp::Sub,
+                rd: 10,
+                rs1: 11,
+                rs2: 12
+            })
+        );
+        // mul a0, a1, a2
+        assert_eq!(
+            decode(0x02c5_8533),
+            Some(Instruction::MulDiv {
+                op: MulDivOp::Mul,
+                rd: 10,
+                rs1: 11,
+                rs2: 12
+            })
+        );
+        // srai a0, a0, 3
+        assert_eq!(
+            decode(0x4035_5513),
+            Some(Instruction::OpImm {
+                op: AluOp::Sra,
+                rd: 10,
+                rs1: 10,
+                imm: 3
+            })
+        );
+        // ret
+        assert_eq!(
+            decode(0x0000_8067),
+            Some(Instruction::Jalr {
+                rd: 0,
+                rs1: 1,
+                offset: 0
+            })
+        );
+        // j +2048
+        assert_eq!(
+            decode(0x0010_006f),
+            Some(Instruction::Jal {
+                rd: 0,
+                offset: 2048
+            })
+        );
+    }
+
+    #[test]
+    fn sign_extends_immediates() {
+        // addi a0, a0, -1
+        assert_eq!(
+            decode(0xfff5_0513),
+            Some(Instruction::OpImm {
+                op: AluOp::Add,
+                rd: 10,
+                rs1: 10,
+                imm: -1
+            })
+        );
+        // sw a1, -4(sp)
+        assert_eq!(
+            decode(0xfeb1_2e23),
+            Some(Instruction::Store {
+                op: StoreOp::Sw,
+                rs1: 2,
+                rs2: 11,
+                offset: -4
+            })
+        );
+        // beq a0, a1, -8
+        assert_eq!(
+            decode(0xfeb5_0ce3),
+            Some(Instruction::Branch {
+                op: BranchOp::Beq,
+                rs1: 10,
+                rs2: 11,
+                offset: -8
+            })
+        );
+        // jal ra, -4
+        assert_eq!(
+            decode(0xffdf_f0ef),
+            Some(Instruction::Jal { rd: 1, offset: -4 })
+        );
+    }
+
+    #[test]
+    fn decodes_system_instructions() {
+        assert_eq!(decode(0x0000_0073), Some(Instruction::Ecall));
+        assert_eq!(decode(0x3020_0073), Some(Instruction::Mret));
+        assert_eq!(decode(0x0ff0_000f), Some(Instruction::Fence));
+        assert_eq!(decode(0x0000_100f), Some(Instruction::FenceI));
+        // csrrw zero, 0x7c0, a0
+        assert_eq!(
+            decode(0x7c05_1073),
+            Some(Instruction::Csr {
+                op: CsrOp::Csrrw,
+                rd: 0,
+                csr: NON_DETERMINISM_CSR,
+                src: CsrSource::Register(10)
+            })
+        );
+        // csrrwi zero, mstatus, 5
+        assert_eq!(
+            decode(0x3002_d073),
+            Some(Instruction::Csr {
+                op: CsrOp::Csrrw,
+                rd: 0,
+                csr: 0x300,
+                src: CsrSource::Immediate(5)
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_illegal_words() {
+        assert_eq!(decode(0x0000_0000), None);
+        assert_eq!(decode(0xffff_ffff), None);
+        // ld a0, 0(a0) is RV64 only.
+        assert_eq!(decode(0x0005_3503), None);
+        // Unused SYSTEM funct3.
+        assert_eq!(decode(0x0000_4073), None);
+        // slli with a shift amount above 31.
+        assert_eq!(decode(0x0205_1513), None);
+    }
+}
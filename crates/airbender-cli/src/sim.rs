@@ -1,35 +1,157 @@
 use anyhow::{Result, bail};
-use risc_v_simulator::abstractions::non_determinism::QuasiUARTSource;
-use risc_v_simulator::cycle::IMStandardIsaConfig;
+use risc_v_simulator::cycle::{
+    IMStandardIsaConfig, IMStandardIsaConfigWithUnsignedMulDiv, MachineConfig,
+};
 use risc_v_simulator::runner::CUSTOM_ENTRY_POINT;
 use risc_v_simulator::setup::BaselineWithND;
 use risc_v_simulator::sim::{
     BinarySource, DiagnosticsConfig, ProfilerConfig, Simulator, SimulatorConfig,
 };
-use std::path::{Path, PathBuf};
+use std::cell::Cell;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::call::{self, FunctionCall};
+use crate::cli::{InputExhausted, Machine};
+use crate::console::{self, GuestPanic};
+use crate::host_oracle::{InputOracle, OracleCommand};
+use crate::markers::{self, MarkerEvent};
+use crate::oracle::{GuestLog, GuestOracle};
+use crate::preload::{self, MemoryRegion};
+use crate::program::Program;
+use crate::riscv;
+use crate::snapshot::{Snapshot, SnapshotRequest};
+use crate::transcript::{self, OracleRead};
+use crate::trap::{self, Trap, TrapDetector, TrapKind};
 
 pub const DEFAULT_CYCLES: usize = 90_000_000_000;
 
+/// `jal x0, 0`: jumps to itself without touching registers or memory.
+const PARK_INSTRUCTION: u32 = 0x0000_006f;
+
+/// Cycles the simulator may spend on the park before it notices the self-loop and ends.
+const MAX_PARKED_CYCLES: usize = 2;
+
 #[derive(Debug)]
 pub struct SimulationOutcome {
     pub registers: [u32; 32],
     pub cycles_executed: usize,
     pub reached_end: bool,
+    pub markers: Vec<MarkerEvent>,
+    pub panic: Option<GuestPanic>,
+    pub trap: Option<Trap>,
+    /// Number of input words the run was given; `None` when a helper process supplied them.
+    pub input_words: Option<usize>,
+    pub oracle_reads: Vec<OracleRead>,
+    /// Whether the guest tried to read past the end of its input.
+    pub input_exhausted: bool,
+}
+
+/// How a run ended, as reported by `run-batch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunStatus {
+    Ok,
+    Trap,
+    Panic,
+    InputExhausted,
+    CycleLimit,
+    /// The run could not be started or the simulator failed.
+    Error,
+}
+
+impl RunStatus {
+    pub fn is_ok(self) -> bool {
+        self == RunStatus::Ok
+    }
+}
+
+impl fmt::Display for RunStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            RunStatus::Ok => "ok",
+            RunStatus::Trap => "trap",
+            RunStatus::Panic => "panic",
+            RunStatus::InputExhausted => "input-exhausted",
+            RunStatus::CycleLimit => "cycle-limit",
+            RunStatus::Error => "error",
+        })
+    }
+}
+
+impl SimulationOutcome {
+    pub fn status(&self) -> RunStatus {
+        if self.trap.is_some() {
+            RunStatus::Trap
+        } else if self.panic.is_some() {
+            RunStatus::Panic
+        } else if self.input_exhausted {
+            RunStatus::InputExhausted
+        } else if !self.reached_end {
+            RunStatus::CycleLimit
+        } else {
+            RunStatus::Ok
+        }
+    }
+}
+
+/// Optional settings for [`run_simulator`].
+#[derive(Default)]
+pub struct SimulatorOptions {
+    pub diagnostics: Option<DiagnosticsConfig>,
+    pub console_output: Option<PathBuf>,
+    pub machine: Machine,
+    pub snapshot: Option<SnapshotRequest>,
+    /// State to start from instead of the entry point; its input words replace `input_words`.
+    pub resume: Option<Snapshot>,
+    /// Helper process that answers input reads instead of `input_words`.
+    pub oracle_command: Option<OracleCommand>,
+    pub on_input_exhausted: InputExhausted,
+    /// Data copied into RAM before the run; not provable.
+    pub preload: Vec<MemoryRegion>,
+    /// Calls a single function instead of starting at the entry point.
+    pub call: Option<FunctionCall>,
+    /// Watches for traps and panic loops; costs an instruction decode per cycle.
+    pub detect_traps: bool,
+}
+
+/// State visible to a [`StepObserver`] right before an instruction is executed.
+pub struct Step<'a> {
+    pub cycle: usize,
+    pub pc: u32,
+    pub instruction: u32,
+    pub registers: &'a [u32; 32],
+    /// Guest memory as words, indexed by `address / 4`.
+    pub memory: &'a [u32],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepAction {
+    Continue,
+    /// Ends the run before the current instruction is executed.
+    Stop,
+}
+
+/// Hook invoked by [`run_simulator`] for every simulated instruction.
+pub trait StepObserver {
+    fn on_step(&mut self, step: &Step<'_>) -> StepAction;
 }
 
 pub fn profiler_diagnostics(
-    app_bin: &Path,
-    elf_path: Option<PathBuf>,
+    program: &Program,
     output: PathBuf,
     sampling_rate: usize,
     inverse: bool,
 ) -> Result<DiagnosticsConfig> {
-    let symbols_path = elf_path.unwrap_or_else(|| derive_elf_path(app_bin));
-    if !symbols_path.exists() {
-        bail!("ELF file not found: {}", symbols_path.display());
-    }
+    let Some(symbols_path) = &program.elf_path else {
+        bail!(
+            "no ELF symbols for {}: pass an ELF or use --elf-path",
+            program.path.display()
+        );
+    };
 
-    let mut diagnostics = DiagnosticsConfig::new(symbols_path);
+    let mut diagnostics = DiagnosticsConfig::new(symbols_path.clone());
     let mut profiler = ProfilerConfig::new(output);
     profiler.frequency_recip = sampling_rate;
     profiler.reverse_graph = inverse;
@@ -38,38 +160,231 @@ pub fn profiler_diagnostics(
 }
 
 pub fn run_simulator(
-    bin_path: &Path,
+    program: &Program,
     input_words: Vec<u32>,
     cycles: usize,
-    diagnostics: Option<DiagnosticsConfig>,
+    options: SimulatorOptions,
+    observers: &mut [&mut dyn StepObserver],
 ) -> Result<SimulationOutcome> {
-    if !bin_path.exists() {
-        bail!("binary not found: {}", bin_path.display());
+    match options.machine {
+        Machine::Standard => {
+            simulate::<IMStandardIsaConfig>(program, input_words, cycles, options, observers)
+        }
+        Machine::UnsignedMulDiv => simulate::<IMStandardIsaConfigWithUnsignedMulDiv>(
+            program,
+            input_words,
+            cycles,
+            options,
+            observers,
+        ),
     }
+}
+
+fn simulate<C: MachineConfig>(
+    program: &Program,
+    input_words: Vec<u32>,
+    cycles: usize,
+    options: SimulatorOptions,
+    observers: &mut [&mut dyn StepObserver],
+) -> Result<SimulationOutcome> {
+    // The simulator copies the binary to the entry point and fetches instructions from memory.
+    // Guests do not write to their own code, so observers decode instructions from the binary
+    // image; the only write there is the park at the end of a stopped run, which no observer sees.
+    let bin_words = program.bin_words()?;
+    let mut trap_detector = (options.detect_traps
+        || options.on_input_exhausted == InputExhausted::Trap)
+        .then(|| TrapDetector::new(program.symbols.as_ref()));
+
+    // Cycles already executed before the resumed snapshot was taken.
+    let offset = options.resume.as_ref().map_or(0, |snapshot| snapshot.cycle);
+    let input_words = match &options.resume {
+        Some(snapshot) => {
+            snapshot.ensure_matches(program, options.machine)?;
+            snapshot.oracle.clone()
+        }
+        None => input_words,
+    };
+
+    if options.oracle_command.is_some() && (options.snapshot.is_some() || options.resume.is_some())
+    {
+        bail!("snapshots are not supported with an oracle helper process");
+    }
+    let input_len = options
+        .oracle_command
+        .is_none()
+        .then_some(input_words.len());
     let config = SimulatorConfig::new(
-        BinarySource::Path(bin_path.to_path_buf()),
+        BinarySource::Slice(bin_words),
         CUSTOM_ENTRY_POINT,
-        cycles,
-        diagnostics,
+        cycles.saturating_sub(offset),
+        options.diagnostics,
     );
-    let non_determinism_source = QuasiUARTSource::new_with_reads(input_words);
-    let setup = BaselineWithND::<_, IMStandardIsaConfig>::new(non_determinism_source);
-    let mut sim = Simulator::<_, IMStandardIsaConfig>::new(config, setup);
+    let guest_log = Arc::new(GuestLog::timed(console::open_sink(
+        options.console_output.as_deref(),
+    )?));
+    let non_determinism_source = GuestOracle::new(
+        InputOracle::new(input_words, options.oracle_command.as_ref())?,
+        guest_log.clone(),
+    );
+    let setup = BaselineWithND::<_, C>::new(non_determinism_source);
+    let mut sim = Simulator::<_, C>::new(config, setup);
+    preload::apply(&options.preload, &mut sim.setup.memory.inner)?;
+    if let Some(call) = &options.call {
+        sim.state.pc = call.entry;
+        sim.state.registers = call.registers;
+    }
+    if let Some(snapshot) = &options.resume {
+        snapshot.restore_memory(&mut sim.setup.memory.inner)?;
+        sim.state.pc = snapshot.pc;
+        sim.state.registers = snapshot.registers;
+        if let Some(trap_detector) = &mut trap_detector {
+            trap_detector.restore_call_stack(&snapshot.call_stack);
+        }
+        tracing::info!("Resuming from cycle {offset} at pc 0x{:08x}", snapshot.pc);
+    }
     let mut last_cycle = 0usize;
-    let result = sim.run(|_, _| {}, |_, cycle| last_cycle = cycle);
-    let cycles_executed = if result.reached_end {
-        last_cycle.saturating_add(1)
-    } else {
-        cycles
+    // State right before the instruction the run stopped at, and the word the park overwrote.
+    let stopped_at = Cell::new(None);
+    let mut parked_word = None;
+    let mut parked_cycles = 0usize;
+    let mut snapshot_result = None;
+    let mut input_exhausted = false;
+    let mut returned = false;
+    let result = sim.run(
+        |sim, cycle| {
+            if stopped_at.get().is_some() {
+                return;
+            }
+            let cycle = offset + cycle;
+            guest_log.set_cycle(cycle);
+            let pc = sim.state.pc;
+            // A frame the guest is halfway through writing is not part of the snapshot, so the
+            // snapshot waits until it is complete.
+            if let Some(request) = &options.snapshot
+                && snapshot_result.is_none()
+                && cycle >= request.cycle
+                && !sim.setup.non_determinism_source.in_frame()
+            {
+                if cycle > request.cycle {
+                    tracing::info!(
+                        "Guest was writing a console or marker frame at cycle {}, snapshot taken at cycle {cycle}",
+                        request.cycle
+                    );
+                }
+                let mut snapshot = Snapshot::capture(
+                    program,
+                    options.machine,
+                    cycle,
+                    pc,
+                    sim.state.registers,
+                    &sim.setup.memory.inner,
+                    sim.setup
+                        .non_determinism_source
+                        .inner()
+                        .remaining_words()
+                        .expect("snapshots need a word list oracle"),
+                );
+                if let Some(trap_detector) = &trap_detector {
+                    snapshot.call_stack = trap_detector.call_stack();
+                }
+                snapshot_result = Some(snapshot.save(&request.path));
+            }
+            let step = Step {
+                cycle,
+                pc,
+                instruction: bin_words.get((pc / 4) as usize).copied().unwrap_or(0),
+                registers: &sim.state.registers,
+                memory: &sim.setup.memory.inner,
+            };
+            // Checked before the read executes, so the report points at the reading instruction.
+            if options.on_input_exhausted != InputExhausted::Zero
+                && riscv::reads_oracle(step.instruction)
+                && sim.setup.non_determinism_source.inner().is_exhausted()
+            {
+                input_exhausted = true;
+                if let Some(trap_detector) = &mut trap_detector
+                    && options.on_input_exhausted == InputExhausted::Trap
+                {
+                    trap_detector.raise(TrapKind::InputExhausted, &step);
+                }
+            }
+            // The called function returned; its return address is not executable.
+            returned |= options.call.is_some() && pc == call::RETURN_SENTINEL;
+            let stop = returned
+                || input_exhausted
+                || trap_detector
+                    .as_mut()
+                    .is_some_and(|detector| detector.on_step(&step) == StepAction::Stop)
+                || observers
+                    .iter_mut()
+                    .any(|observer| observer.on_step(&step) == StepAction::Stop);
+            if stop {
+                stopped_at.set(Some((cycle, sim.state.registers)));
+                parked_word = Some(park(&mut sim.setup.memory.inner));
+                sim.state.pc = CUSTOM_ENTRY_POINT;
+            }
+        },
+        |_, cycle| {
+            if stopped_at.get().is_none() {
+                last_cycle = offset + cycle;
+            } else {
+                parked_cycles += 1;
+            }
+        },
+    );
+    if let Some(word) = parked_word {
+        sim.setup.memory.inner[(CUSTOM_ENTRY_POINT / 4) as usize] = word;
+    }
+    if parked_cycles > MAX_PARKED_CYCLES {
+        bail!(
+            "the simulator kept running for {parked_cycles} cycles after the run was stopped; \
+             it no longer ends on a jump to itself"
+        );
+    }
+
+    sim.setup.non_determinism_source.inner().check()?;
+    if let Some(request) = &options.snapshot {
+        match snapshot_result {
+            Some(saved) => saved?,
+            None => tracing::warn!(
+                "Run ended before cycle {}, no snapshot was written",
+                request.cycle
+            ),
+        }
+    }
+
+    let (registers, cycles_executed, reached_end) = match stopped_at.get() {
+        Some((cycle, registers)) => (registers, cycle, returned),
+        None if result.reached_end => (result.state.registers, last_cycle.saturating_add(1), true),
+        None => (result.state.registers, cycles, false),
     };
+    let trap = trap_detector.and_then(|detector| detector.finish(reached_end));
+    let reached_end = reached_end && trap.is_none();
 
     Ok(SimulationOutcome {
-        registers: result.state.registers,
+        registers,
         cycles_executed,
-        reached_end: result.reached_end,
+        reached_end,
+        markers: guest_log.take_markers(),
+        panic: guest_log.guest_panic(),
+        trap,
+        input_words: input_len,
+        oracle_reads: guest_log.take_reads(),
+        input_exhausted: input_exhausted && options.on_input_exhausted == InputExhausted::Error,
     })
 }
 
+/// Ends the run without executing another guest instruction: the simulator has no way to abort,
+/// so the entry point word is replaced by [`PARK_INSTRUCTION`] and the PC moved there. This
+/// relies on two simulator internals: instructions are fetched from `memory`, not from a copy of
+/// the binary, and a jump to itself that changes nothing ends the run like a finished guest.
+/// [`simulate`] fails when a stopped run keeps going for more than [`MAX_PARKED_CYCLES`].
+/// Returns the overwritten word so it can be restored once the run is over.
+fn park(memory: &mut [u32]) -> u32 {
+    let slot = &mut memory[(CUSTOM_ENTRY_POINT / 4) as usize];
+    std::mem::replace(slot, PARK_INSTRUCTION)
+}
+
 pub fn report_run_outcome(outcome: &SimulationOutcome) {
     tracing::info!(
         "Execution finished: cycles_executed: {}, reached_end: {}",
@@ -81,10 +396,88 @@ pub fn report_run_outcome(outcome: &SimulationOutcome) {
         registers_str.push_str(&format!("x{}={} ", 10 + idx, value));
     }
     tracing::info!("Output values: {}", registers_str.trim());
+    if let Some(trap) = &outcome.trap {
+        trap::report_trap(trap);
+    }
+    if outcome.input_exhausted {
+        tracing::error!("Guest tried to read past the end of its input");
+    }
+    if let Some(panic) = &outcome.panic {
+        match &panic.location {
+            Some(location) => tracing::error!("Guest panicked at {location}: {}", panic.message),
+            None => tracing::error!("Guest panicked: {}", panic.message),
+        }
+    }
+    if !outcome.markers.is_empty() {
+        markers::report_regions(&outcome.markers, outcome.cycles_executed);
+    }
+    if let Some(provided) = outcome.input_words {
+        transcript::report_consumption(provided, outcome.oracle_reads.len());
+    }
 }
 
-fn derive_elf_path(bin_path: &Path) -> PathBuf {
-    let mut elf_path = bin_path.to_path_buf();
-    elf_path.set_extension("elf");
-    elf_path
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Stops the run at `cycle` and remembers the registers it saw there.
+    struct StopAt {
+        cycle: usize,
+        registers: Option<[u32; 32]>,
+    }
+
+    impl StepObserver for StopAt {
+        fn on_step(&mut self, step: &Step<'_>) -> StepAction {
+            if step.cycle < self.cycle {
+                return StepAction::Continue;
+            }
+            self.registers = Some(*step.registers);
+            StepAction::Stop
+        }
+    }
+
+    #[test]
+    fn park_is_a_jump_to_itself() {
+        assert_eq!(
+            riscv::decode(PARK_INSTRUCTION),
+            Some(riscv::Instruction::Jal { rd: 0, offset: 0 })
+        );
+        let slot = (CUSTOM_ENTRY_POINT / 4) as usize;
+        let mut memory = vec![0; slot + 2];
+        memory[slot] = 0x0015_0513;
+        assert_eq!(park(&mut memory), 0x0015_0513);
+        assert_eq!(memory[slot], PARK_INSTRUCTION);
+    }
+
+    #[test]
+    fn stopping_ends_the_run_on_the_park() {
+        // loop: addi a0, a0, 1; j loop
+        let bin: Vec<u8> = [0x0015_0513u32, 0xffdf_f06f]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        let path =
+            std::env::temp_dir().join(format!("airbender-cli-{}-park.bin", std::process::id()));
+        fs::write(&path, bin).unwrap();
+        let program = Program::load(&path, None);
+        fs::remove_file(&path).unwrap();
+
+        let mut stop = StopAt {
+            cycle: 100,
+            registers: None,
+        };
+        let outcome = run_simulator(
+            &program.unwrap(),
+            Vec::new(),
+            1_000_000,
+            SimulatorOptions::default(),
+            &mut [&mut stop],
+        )
+        .unwrap();
+        // `run_simulator` fails when the loop keeps running on the park until the cycle limit.
+        assert_eq!(Some(outcome.registers), stop.registers);
+        assert_eq!(outcome.cycles_executed, 100);
+        assert!(!outcome.reached_end);
+    }
 }
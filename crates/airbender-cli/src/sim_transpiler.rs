@@ -1,32 +1,81 @@
 use anyhow::{Result, bail};
-use risc_v_simulator::abstractions::non_determinism::QuasiUARTSource;
 use riscv_transpiler::common_constants::{INITIAL_TIMESTAMP, TIMESTAMP_STEP};
 use riscv_transpiler::jit::JittedCode;
-use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::sync::Arc;
 use tracing::warn;
 
+use crate::cli::{InputExhausted, Machine};
+use crate::console;
+use crate::host_oracle::{InputOracle, OracleCommand};
+use crate::oracle::{GuestLog, GuestOracle};
+use crate::preload::{self, MemoryRegion};
+use crate::program::Program;
 use crate::sim::SimulationOutcome;
 
+/// The only machine the JIT implements; it has no signed multiplication or division.
+pub const JIT_MACHINE: Machine = Machine::UnsignedMulDiv;
+
+/// Optional settings for [`run_transpiler`].
+#[derive(Default)]
+pub struct TranspilerOptions {
+    pub console_output: Option<PathBuf>,
+    /// Helper process that answers input reads instead of `input_words`.
+    pub oracle_command: Option<OracleCommand>,
+    pub on_input_exhausted: InputExhausted,
+    /// Data copied into RAM before the run; not provable.
+    pub preload: Vec<MemoryRegion>,
+    /// Machine the caller expects; anything but [`JIT_MACHINE`] is rejected.
+    pub machine: Machine,
+}
+
 pub fn run_transpiler(
-    bin_path: &Path,
+    program: &Program,
     input_words: Vec<u32>,
     cycles: usize,
-    text_path: Option<&PathBuf>,
+    options: TranspilerOptions,
 ) -> Result<SimulationOutcome> {
-    if !bin_path.exists() {
-        bail!("binary not found: {}", bin_path.display());
+    if options.machine != JIT_MACHINE {
+        bail!(
+            "the JIT only implements {JIT_MACHINE}, got {}; use the simulator",
+            options.machine
+        );
     }
-    let text_path = text_path
-        .cloned()
-        .unwrap_or_else(|| derive_text_path(bin_path));
-    if !text_path.exists() {
-        bail!("text file not found: {}", text_path.display());
+    if options.on_input_exhausted == InputExhausted::Trap {
+        bail!("the JIT cannot stop at the read, so --on-input-exhausted trap needs the simulator");
     }
+    let bin_words = program.bin_words()?;
+    let text_words = program.text_words()?;
 
-    let bin_words = read_u32_words(bin_path)?;
-    let text_words = read_u32_words(&text_path)?;
+    // The binary image is the JIT's initial memory, so preloaded regions are placed into a copy.
+    let initial_memory = if options.preload.is_empty() {
+        Cow::Borrowed(bin_words)
+    } else {
+        let end = options
+            .preload
+            .iter()
+            .map(|region| (region.address / 4) as usize + region.words.len())
+            .max()
+            .unwrap_or(0);
+        let mut memory = bin_words.to_vec();
+        memory.resize(memory.len().max(end), 0);
+        preload::apply(&options.preload, &mut memory)?;
+        Cow::Owned(memory)
+    };
 
-    let mut non_determinism_source = QuasiUARTSource::new_with_reads(input_words);
+    let input_len = options
+        .oracle_command
+        .is_none()
+        .then_some(input_words.len());
+    // The JIT exposes no per-instruction hook, so markers are recorded without cycles.
+    let guest_log = Arc::new(GuestLog::new(console::open_sink(
+        options.console_output.as_deref(),
+    )?));
+    let mut non_determinism_source = GuestOracle::new(
+        InputOracle::new(input_words, options.oracle_command.as_ref())?,
+        guest_log.clone(),
+    );
 
     let cycles_bound = match u32::try_from(cycles) {
         Ok(value) => Some(value),
@@ -40,38 +89,29 @@ pub fn run_transpiler(
     };
 
     let (state, _memory) = JittedCode::run_alternative_simulator(
-        &text_words,
+        text_words,
         &mut non_determinism_source,
-        &bin_words,
+        &initial_memory,
         cycles_bound,
     );
 
+    non_determinism_source.inner().check()?;
+
     let cycles_executed = ((state.timestamp - INITIAL_TIMESTAMP) / TIMESTAMP_STEP) as usize;
+    // The JIT stops silently at the cycle bound, so a run that used all of it did not finish.
+    let reached_end = cycles_bound.is_none_or(|bound| cycles_executed < bound as usize);
 
     Ok(SimulationOutcome {
         registers: state.registers,
         cycles_executed,
-        reached_end: true,
+        reached_end,
+        markers: guest_log.take_markers(),
+        panic: guest_log.guest_panic(),
+        trap: None,
+        input_words: input_len,
+        oracle_reads: guest_log.take_reads(),
+        // The JIT cannot stop at the read, so running dry is only reported afterwards.
+        input_exhausted: options.on_input_exhausted != InputExhausted::Zero
+            && non_determinism_source.inner().ran_dry(),
     })
 }
-
-fn derive_text_path(bin_path: &Path) -> PathBuf {
-    let mut text_path = bin_path.to_path_buf();
-    text_path.set_extension("text");
-    text_path
-}
-
-fn read_u32_words(path: &Path) -> Result<Vec<u32>> {
-    use std::io::Read;
-    let mut file = std::fs::File::open(path)?;
-    let mut buffer = vec![];
-    file.read_to_end(&mut buffer)?;
-    if buffer.len() % 4 != 0 {
-        bail!("file length is not a multiple of 4: {}", path.display());
-    }
-    let mut words = Vec::with_capacity(buffer.len() / 4);
-    for chunk in buffer.as_chunks::<4>().0 {
-        words.push(u32::from_le_bytes(*chunk));
-    }
-    Ok(words)
-}
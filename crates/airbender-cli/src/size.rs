@@ -0,0 +1,188 @@
+//! ROM usage breakdown by symbol, similar to `cargo bloat`.
+
+use anyhow::{Context, Result, bail};
+use object::{Object, ObjectSection, ObjectSymbol, SectionIndex};
+use riscv_transpiler::common_constants::rom::ROM_BYTE_SIZE;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::program::Program;
+
+/// Name used for symbols without a Rust path, such as `memcpy` or hand-written assembly.
+const UNKNOWN_CRATE: &str = "[unknown]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Section {
+    Text,
+    Rodata,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SizeReport {
+    pub rom_byte_size: usize,
+    pub bin_size: usize,
+    pub text_size: u64,
+    pub rodata_size: u64,
+    /// Symbols (or crates, when grouped) sorted by size, largest first.
+    pub entries: Vec<SizeEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SizeEntry {
+    pub name: String,
+    pub section: Section,
+    pub size: u64,
+    /// Share of `ROM_BYTE_SIZE`.
+    pub rom_percent: f64,
+}
+
+/// Reports how much of the ROM each function (or crate, with `by_crate`) takes.
+pub fn report(
+    program: &Program,
+    by_crate: bool,
+    top: usize,
+    json_output: Option<&Path>,
+) -> Result<()> {
+    let report = build_report(program, by_crate)?;
+
+    tracing::info!(
+        "ROM: {} of {ROM_BYTE_SIZE} bytes used ({:.2}%), .text {} bytes, .rodata {} bytes",
+        report.bin_size,
+        rom_percent(report.bin_size as u64),
+        report.text_size,
+        report.rodata_size
+    );
+    tracing::info!("{:>10} {:>7}  {:<7} Name", "Size", "ROM %", "Section");
+    for entry in report.entries.iter().take(top) {
+        tracing::info!(
+            "{:>10} {:>6.2}%  {:<7} {}",
+            entry.size,
+            entry.rom_percent,
+            match entry.section {
+                Section::Text => ".text",
+                Section::Rodata => ".rodata",
+            },
+            entry.name
+        );
+    }
+    if report.entries.len() > top {
+        tracing::info!("... and {} more", report.entries.len() - top);
+    }
+
+    if let Some(path) = json_output {
+        let encoded = serde_json::to_string_pretty(&report)?;
+        fs::write(path, encoded)
+            .with_context(|| format!("failed to write size report to {}", path.display()))?;
+        tracing::info!("Size report written to {}", path.display());
+    }
+    Ok(())
+}
+
+fn build_report(program: &Program, by_crate: bool) -> Result<SizeReport> {
+    let Some(elf_path) = &program.elf_path else {
+        bail!(
+            "no ELF symbols for {}: pass an ELF or use --elf",
+            program.path.display()
+        );
+    };
+    let data =
+        fs::read(elf_path).with_context(|| format!("failed to read {}", elf_path.display()))?;
+    let file = object::File::parse(&*data)
+        .with_context(|| format!("failed to parse ELF {}", elf_path.display()))?;
+
+    let mut sections: HashMap<SectionIndex, Section> = HashMap::new();
+    let mut section_sizes: BTreeMap<Section, u64> = BTreeMap::new();
+    for section in file.sections() {
+        let name = section.name().unwrap_or_default();
+        let kind = if name == ".text" || name.starts_with(".text.") {
+            Section::Text
+        } else if name.starts_with(".rodata") || name.starts_with(".srodata") {
+            Section::Rodata
+        } else {
+            continue;
+        };
+        sections.insert(section.index(), kind);
+        *section_sizes.entry(kind).or_default() += section.size();
+    }
+
+    // Aliases share an address, so each (section, address) pair is counted once.
+    let mut symbols: BTreeMap<(Section, u64), (String, u64)> = BTreeMap::new();
+    for symbol in file.symbols() {
+        let Some(&section) = symbol.section_index().and_then(|idx| sections.get(&idx)) else {
+            continue;
+        };
+        let Ok(name) = symbol.name() else {
+            continue;
+        };
+        if name.is_empty() || symbol.size() == 0 {
+            continue;
+        }
+        symbols
+            .entry((section, symbol.address()))
+            .or_insert_with(|| {
+                (
+                    format!("{:#}", rustc_demangle::demangle(name)),
+                    symbol.size(),
+                )
+            });
+    }
+
+    let mut totals: HashMap<(Section, String), u64> = HashMap::new();
+    let mut attributed: BTreeMap<Section, u64> = BTreeMap::new();
+    for ((section, _), (name, size)) in symbols {
+        *attributed.entry(section).or_default() += size;
+        let key = if by_crate {
+            crate_name(&name).to_string()
+        } else {
+            name
+        };
+        *totals.entry((section, key)).or_default() += size;
+    }
+    for (&section, &size) in &section_sizes {
+        let unattributed = size.saturating_sub(attributed.get(&section).copied().unwrap_or(0));
+        if unattributed > 0 {
+            totals.insert((section, "[unattributed]".to_string()), unattributed);
+        }
+    }
+
+    let mut entries: Vec<SizeEntry> = totals
+        .into_iter()
+        .map(|((section, name), size)| SizeEntry {
+            name,
+            section,
+            size,
+            rom_percent: rom_percent(size),
+        })
+        .collect();
+    entries.sort_by(|a, b| {
+        b.size
+            .cmp(&a.size)
+            .then_with(|| a.section.cmp(&b.section))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    Ok(SizeReport {
+        rom_byte_size: ROM_BYTE_SIZE,
+        bin_size: program.bin.len(),
+        text_size: section_sizes.get(&Section::Text).copied().unwrap_or(0),
+        rodata_size: section_sizes.get(&Section::Rodata).copied().unwrap_or(0),
+        entries,
+    })
+}
+
+/// Returns the crate a demangled Rust path belongs to, e.g. `core` for
+/// `<core::fmt::Arguments as core::fmt::Display>::fmt`.
+fn crate_name(name: &str) -> &str {
+    let path = name.trim_start_matches(['<', '&', '*']);
+    match path.split_once("::") {
+        Some((krate, _)) if !krate.is_empty() && !krate.contains([' ', '<', '>']) => krate,
+        _ => UNKNOWN_CRATE,
+    }
+}
+
+fn rom_percent(size: u64) -> f64 {
+    size as f64 * 100.0 / ROM_BYTE_SIZE as f64
+}
@@ -0,0 +1,239 @@
+//! Simulator snapshots: machine state at a given cycle, so a run can be resumed from there.
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use sha3::Digest;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::Machine;
+use crate::program::Program;
+
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// Memory is stored in pages of this many words; all-zero pages are left out.
+const PAGE_WORDS: usize = 1024;
+
+/// Where and when [`crate::sim::run_simulator`] takes a snapshot.
+#[derive(Debug, Clone)]
+pub struct SnapshotRequest {
+    pub cycle: usize,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// Keccak256 of the ROM image, to refuse resuming a different program.
+    pub app_bin_hash: [u8; 32],
+    pub machine: Machine,
+    /// Cycle of the next instruction to execute.
+    pub cycle: usize,
+    pub pc: u32,
+    pub registers: [u32; 32],
+    /// Non-zero memory pages as (index of the first word, words).
+    pub pages: Vec<(u32, Vec<u32>)>,
+    /// Input words the guest has not read yet.
+    pub oracle: Vec<u32>,
+    /// Trap detector's shadow call stack as (call site, return address), outermost first;
+    /// empty when the run did not detect traps.
+    pub call_stack: Vec<(u32, u32)>,
+}
+
+impl Snapshot {
+    pub fn capture(
+        program: &Program,
+        machine: Machine,
+        cycle: usize,
+        pc: u32,
+        registers: [u32; 32],
+        memory: &[u32],
+        oracle: Vec<u32>,
+    ) -> Self {
+        let pages = memory
+            .chunks(PAGE_WORDS)
+            .enumerate()
+            .filter(|(_, page)| page.iter().any(|&word| word != 0))
+            .map(|(idx, page)| ((idx * PAGE_WORDS) as u32, page.to_vec()))
+            .collect();
+        Self {
+            version: SNAPSHOT_VERSION,
+            app_bin_hash: app_bin_hash(program),
+            machine,
+            cycle,
+            pc,
+            registers,
+            pages,
+            oracle,
+            call_stack: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)
+            .with_context(|| format!("failed to read snapshot {}", path.display()))?;
+        let (snapshot, _): (Self, usize) =
+            bincode::serde::decode_from_slice(&bytes, bincode::config::standard())
+                .with_context(|| format!("failed to decode snapshot {}", path.display()))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            bail!(
+                "snapshot {} has version {}, expected {SNAPSHOT_VERSION}",
+                path.display(),
+                snapshot.version
+            );
+        }
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let encoded = bincode::serde::encode_to_vec(self, bincode::config::standard())?;
+        fs::write(path, encoded)
+            .with_context(|| format!("failed to write snapshot to {}", path.display()))?;
+        tracing::info!(
+            "Snapshot at cycle {} written to {} ({} memory pages, {} input words left)",
+            self.cycle,
+            path.display(),
+            self.pages.len(),
+            self.oracle.len()
+        );
+        Ok(())
+    }
+
+    /// Fails unless the snapshot was taken from `program` on `machine`.
+    pub fn ensure_matches(&self, program: &Program, machine: Machine) -> Result<()> {
+        if self.app_bin_hash != app_bin_hash(program) {
+            bail!(
+                "snapshot was taken from a different binary than {}",
+                program.path.display()
+            );
+        }
+        if self.machine != machine {
+            bail!(
+                "snapshot was taken on {}, but --machine is {machine}",
+                self.machine
+            );
+        }
+        Ok(())
+    }
+
+    /// Replaces the contents of `memory` with the snapshot's.
+    pub fn restore_memory(&self, memory: &mut [u32]) -> Result<()> {
+        memory.fill(0);
+        for (start, words) in &self.pages {
+            let start = *start as usize;
+            let Some(target) = memory.get_mut(start..start + words.len()) else {
+                bail!("snapshot memory page at word {start} is outside the simulator's memory");
+            };
+            target.copy_from_slice(words);
+        }
+        Ok(())
+    }
+}
+
+fn app_bin_hash(program: &Program) -> [u8; 32] {
+    sha3::Keccak256::digest(&program.bin).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_program(name: &str, bin: &[u8]) -> (Program, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("airbender-cli-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.bin");
+        fs::write(&path, bin).unwrap();
+        let program = Program::load(&path, None).unwrap();
+        (program, dir)
+    }
+
+    fn memory() -> Vec<u32> {
+        let mut memory = vec![0; 4 * PAGE_WORDS];
+        memory[3] = 0x1234;
+        memory[2 * PAGE_WORDS + 5] = 0xdead_beef;
+        memory
+    }
+
+    #[test]
+    fn stores_only_non_zero_pages() {
+        let (program, dir) = test_program("snapshot-pages", &[0x13, 0, 0, 0]);
+        fs::remove_dir_all(&dir).unwrap();
+        let snapshot = Snapshot::capture(
+            &program,
+            Machine::UnsignedMulDiv,
+            10,
+            0x40,
+            [0; 32],
+            &memory(),
+            Vec::new(),
+        );
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        let starts: Vec<u32> = snapshot.pages.iter().map(|(start, _)| *start).collect();
+        assert_eq!(starts, [0, 2 * PAGE_WORDS as u32]);
+        assert!(
+            snapshot
+                .pages
+                .iter()
+                .all(|(_, words)| words.len() == PAGE_WORDS)
+        );
+
+        let mut restored = vec![7; 4 * PAGE_WORDS];
+        snapshot.restore_memory(&mut restored).unwrap();
+        assert_eq!(restored, memory());
+        assert!(snapshot.restore_memory(&mut [0; PAGE_WORDS]).is_err());
+    }
+
+    #[test]
+    fn round_trips_state_and_remaining_input() {
+        let (program, dir) = test_program("snapshot-round-trip", &[0x13, 0, 0, 0]);
+        let mut registers = [0; 32];
+        registers[10] = 42;
+        let mut snapshot = Snapshot::capture(
+            &program,
+            Machine::Standard,
+            1000,
+            0x80,
+            registers,
+            &memory(),
+            vec![5, 6, 7],
+        );
+        snapshot.call_stack = vec![(0x10, 0x14)];
+        let path = dir.join("state.snap");
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path);
+
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        snapshot.save(&path).unwrap();
+        let other_version = Snapshot::load(&path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.cycle, 1000);
+        assert_eq!(loaded.pc, 0x80);
+        assert_eq!(loaded.registers, registers);
+        assert_eq!(loaded.oracle, [5, 6, 7]);
+        assert_eq!(loaded.call_stack, [(0x10, 0x14)]);
+        assert_eq!(loaded.pages, snapshot.pages);
+        let message = other_version.unwrap_err().to_string();
+        assert!(message.contains("expected 2"), "{message}");
+    }
+
+    #[test]
+    fn rejects_other_binaries_and_machines() {
+        let (program, dir) = test_program("snapshot-matches", &[0x13, 0, 0, 0]);
+        let (other, other_dir) = test_program("snapshot-other", &[0x6f, 0, 0, 0]);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&other_dir).unwrap();
+        let snapshot =
+            Snapshot::capture(&program, Machine::Standard, 0, 0, [0; 32], &[], Vec::new());
+        snapshot
+            .ensure_matches(&program, Machine::Standard)
+            .unwrap();
+        assert!(snapshot.ensure_matches(&other, Machine::Standard).is_err());
+        assert!(
+            snapshot
+                .ensure_matches(&program, Machine::UnsignedMulDiv)
+                .is_err()
+        );
+    }
+}
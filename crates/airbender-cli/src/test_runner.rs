@@ -0,0 +1,377 @@
+//! Declarative test manifests: run guest cases and check outputs and cycle counts.
+//!
+//! ```toml
+//! [[case]]
+//! name = "add"
+//! binary = "guests/add.bin"  # relative to the manifest
+//! input = "vectors/add.hex"  # optional
+//! outputs = [3, 0x10]        # expected x10, x11, ...
+//! cycles = { min = 100, max = 2000 }
+//! engine = "both"            # sim (default), jit or both
+//! ```
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::cli::{Engine, Machine};
+use crate::console;
+use crate::input;
+use crate::program::Program;
+use crate::sim::{self, SimulationOutcome, SimulatorOptions};
+use crate::sim_transpiler::{self, TranspilerOptions};
+use crate::xml;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(rename = "case", default)]
+    pub cases: Vec<TestCase>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    pub binary: PathBuf,
+    pub input: Option<PathBuf>,
+    /// Expected values of x10, x11, ...; registers past the end are not checked.
+    #[serde(default)]
+    pub outputs: Vec<u32>,
+    pub cycles: Option<CycleRange>,
+    #[serde(default)]
+    pub engine: TestEngine,
+    #[serde(default)]
+    pub machine: Machine,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CycleRange {
+    #[serde(default)]
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestEngine {
+    #[default]
+    Sim,
+    Jit,
+    Both,
+}
+
+#[derive(Debug)]
+pub struct TestResult {
+    /// Case name with the engine, e.g. `add [jit]`.
+    pub name: String,
+    pub cycles: Option<usize>,
+    pub elapsed_secs: f64,
+    /// Reasons the case failed; empty when it passed.
+    pub failures: Vec<String>,
+    /// Set when the case could not be run at all.
+    pub error: Option<String>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty() && self.error.is_none()
+    }
+}
+
+pub fn load_manifest(path: &Path) -> Result<Manifest> {
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("failed to read manifest {}", path.display()))?;
+    toml::from_str(&raw).with_context(|| format!("invalid manifest {}", path.display()))
+}
+
+/// Runs every case of the manifest at `path`, logging each result as it completes.
+pub fn run_manifest(path: &Path) -> Result<Vec<TestResult>> {
+    let manifest = load_manifest(path)?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let mut programs: HashMap<PathBuf, Program> = HashMap::new();
+    let mut results = Vec::new();
+
+    for case in &manifest.cases {
+        let binary = base_dir.join(&case.binary);
+        if !programs.contains_key(&binary) {
+            match Program::load(&binary, None) {
+                Ok(program) => {
+                    programs.insert(binary.clone(), program);
+                }
+                Err(err) => {
+                    for engine in engines(case.engine) {
+                        let result = TestResult {
+                            name: format!("{} [{engine}]", case.name),
+                            cycles: None,
+                            elapsed_secs: 0.0,
+                            failures: Vec::new(),
+                            error: Some(format!("{err:#}")),
+                        };
+                        log_result(&result);
+                        results.push(result);
+                    }
+                    continue;
+                }
+            }
+        }
+        let program = &programs[&binary];
+
+        let mut outputs_by_engine = Vec::new();
+        for &engine in engines(case.engine) {
+            let start = Instant::now();
+            let outcome = run_case(program, case, base_dir, engine);
+            let mut result = TestResult {
+                name: format!("{} [{engine}]", case.name),
+                cycles: None,
+                elapsed_secs: start.elapsed().as_secs_f64(),
+                failures: Vec::new(),
+                error: None,
+            };
+            match outcome {
+                Ok(outcome) => {
+                    result.cycles = Some(outcome.cycles_executed);
+                    result.failures = check_outcome(case, &outcome);
+                    if let Some((other, registers)) = outputs_by_engine.first()
+                        && *registers != outcome.registers
+                    {
+                        result
+                            .failures
+                            .push(format!("registers differ from the {other} run"));
+                    }
+                    outputs_by_engine.push((engine, outcome.registers));
+                }
+                Err(err) => result.error = Some(format!("{err:#}")),
+            }
+            log_result(&result);
+            results.push(result);
+        }
+    }
+    Ok(results)
+}
+
+fn engines(engine: TestEngine) -> &'static [Engine] {
+    match engine {
+        TestEngine::Sim => &[Engine::Sim],
+        TestEngine::Jit => &[Engine::Jit],
+        TestEngine::Both => &[Engine::Sim, Engine::Jit],
+    }
+}
+
+fn run_case(
+    program: &Program,
+    case: &TestCase,
+    base_dir: &Path,
+    engine: Engine,
+) -> Result<SimulationOutcome> {
+    let input_words = match &case.input {
+        Some(input) => input::parse_input_words(&base_dir.join(input))?,
+        None => Vec::new(),
+    };
+    // Stop just past the allowed range instead of running to the global default.
+    let cycles = case
+        .cycles
+        .and_then(|range| range.max)
+        .map_or(sim::DEFAULT_CYCLES, |max| max.saturating_add(1));
+    let console_output = Path::new(console::NULL_DEVICE);
+    match engine {
+        Engine::Jit => {
+            let options = TranspilerOptions {
+                console_output: Some(console_output.to_path_buf()),
+                machine: case.machine,
+                ..Default::default()
+            };
+            sim_transpiler::run_transpiler(program, input_words, cycles, options)
+        }
+        Engine::Sim => {
+            let options = SimulatorOptions {
+                console_output: Some(console_output.to_path_buf()),
+                machine: case.machine,
+                detect_traps: true,
+                ..Default::default()
+            };
+            sim::run_simulator(program, input_words, cycles, options, &mut [])
+        }
+    }
+}
+
+fn check_outcome(case: &TestCase, outcome: &SimulationOutcome) -> Vec<String> {
+    let mut failures = Vec::new();
+    if let Some(trap) = &outcome.trap {
+        failures.push(format!(
+            "guest trapped: {} at {}",
+            trap.kind, trap.backtrace[0]
+        ));
+    }
+    if let Some(panic) = &outcome.panic {
+        failures.push(format!("guest panicked: {}", panic.message));
+    }
+    if outcome.input_exhausted {
+        failures.push("guest read past the end of its input".to_string());
+    } else if outcome.trap.is_none() && !outcome.reached_end {
+        failures.push(format!(
+            "did not finish within {} cycles",
+            outcome.cycles_executed
+        ));
+    }
+
+    for (idx, expected) in case.outputs.iter().enumerate() {
+        let register = 10 + idx;
+        let actual = outcome.registers.get(register).copied();
+        if actual != Some(*expected) {
+            failures.push(format!(
+                "x{register}: expected {expected} (0x{expected:x}), got {}",
+                actual.map_or("nothing".to_string(), |value| format!(
+                    "{value} (0x{value:x})"
+                ))
+            ));
+        }
+    }
+
+    if let Some(range) = case.cycles {
+        let cycles = outcome.cycles_executed;
+        if cycles < range.min || range.max.is_some_and(|max| cycles > max) {
+            let max = range.max.map_or("∞".to_string(), |max| max.to_string());
+            failures.push(format!("cycles {cycles} outside {}..={max}", range.min));
+        }
+    }
+    failures
+}
+
+fn log_result(result: &TestResult) {
+    let cycles = result
+        .cycles
+        .map_or(String::new(), |cycles| format!(" ({cycles} cycles)"));
+    if result.passed() {
+        tracing::info!("PASS {}{cycles}", result.name);
+        return;
+    }
+    tracing::error!("FAIL {}{cycles}", result.name);
+    for failure in result.failures.iter().chain(&result.error) {
+        tracing::error!("    {failure}");
+    }
+}
+
+pub fn report(results: &[TestResult]) {
+    let passed = results.iter().filter(|result| result.passed()).count();
+    tracing::info!(
+        "{passed} passed, {} failed, {} total",
+        results.len() - passed,
+        results.len()
+    );
+}
+
+pub fn write_junit(results: &[TestResult], suite: &str, path: &Path) -> Result<()> {
+    let failures = results
+        .iter()
+        .filter(|result| result.error.is_none() && !result.failures.is_empty())
+        .count();
+    let errors = results
+        .iter()
+        .filter(|result| result.error.is_some())
+        .count();
+    let time: f64 = results.iter().map(|result| result.elapsed_secs).sum();
+    let suite = xml::escape(suite);
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        xml,
+        r#"<testsuites tests="{}" failures="{failures}" errors="{errors}" time="{time:.3}">"#,
+        results.len()
+    )
+    .unwrap();
+    writeln!(
+        xml,
+        r#"  <testsuite name="{suite}" tests="{}" failures="{failures}" errors="{errors}" time="{time:.3}">"#,
+        results.len()
+    )
+    .unwrap();
+    for result in results {
+        writeln!(
+            xml,
+            r#"    <testcase name="{}" classname="{suite}" time="{:.3}">"#,
+            xml::escape(&result.name),
+            result.elapsed_secs
+        )
+        .unwrap();
+        if let Some(error) = &result.error {
+            writeln!(xml, r#"      <error message="{}"/>"#, xml::escape(error)).unwrap();
+        } else if !result.failures.is_empty() {
+            let details = xml::escape(&result.failures.join("\n"));
+            writeln!(
+                xml,
+                r#"      <failure message="{}">{details}</failure>"#,
+                xml::escape(&result.failures[0])
+            )
+            .unwrap();
+        }
+        if let Some(cycles) = result.cycles {
+            writeln!(xml, "      <system-out>cycles={cycles}</system-out>").unwrap();
+        }
+        writeln!(xml, "    </testcase>").unwrap();
+    }
+    writeln!(xml, "  </testsuite>").unwrap();
+    writeln!(xml, "</testsuites>").unwrap();
+
+    fs::write(path, xml).with_context(|| format!("failed to write {}", path.display()))?;
+    tracing::info!("JUnit report written to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_junit_failures_and_errors() {
+        let results = [
+            TestResult {
+                name: "add [sim]".to_string(),
+                cycles: Some(120),
+                elapsed_secs: 0.5,
+                failures: Vec::new(),
+                error: None,
+            },
+            TestResult {
+                name: "add [jit]".to_string(),
+                cycles: Some(3),
+                elapsed_secs: 0.25,
+                failures: vec![
+                    "x10: expected 3 (0x3), got 4 (0x4)".to_string(),
+                    "a < b".to_string(),
+                ],
+                error: None,
+            },
+            TestResult {
+                name: "missing".to_string(),
+                cycles: None,
+                elapsed_secs: 0.0,
+                failures: Vec::new(),
+                error: Some("failed to read \"missing.bin\"".to_string()),
+            },
+        ];
+        let path =
+            std::env::temp_dir().join(format!("airbender-cli-{}-junit.xml", std::process::id()));
+        write_junit(&results, "guests & co", &path).unwrap();
+        let xml = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(xml.contains(r#"<testsuites tests="3" failures="1" errors="1" time="0.750">"#));
+        assert!(xml.contains(r#"<testsuite name="guests &amp; co" tests="3""#));
+        assert!(
+            xml.contains(r#"<testcase name="add [sim]" classname="guests &amp; co" time="0.500">"#)
+        );
+        assert!(xml.contains(
+            r#"<failure message="x10: expected 3 (0x3), got 4 (0x4)">x10: expected 3 (0x3), got 4 (0x4)
+a &lt; b</failure>"#
+        ));
+        assert!(xml.contains(r#"<error message="failed to read &quot;missing.bin&quot;"/>"#));
+        assert!(xml.contains("<system-out>cycles=120</system-out>"));
+    }
+}
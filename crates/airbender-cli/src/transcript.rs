@@ -0,0 +1,146 @@
+//! Transcripts of the words the guest read from the input oracle.
+//!
+//! One read per line: `<cycle> <index> <value>`, with the value in hex and `-` as the cycle
+//! when the runner cannot attribute cycles (the JIT).
+
+use anyhow::{Context, Result, bail};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OracleRead {
+    pub cycle: Option<usize>,
+    /// Position of the word in the input stream.
+    pub index: usize,
+    pub value: u32,
+}
+
+pub fn write(reads: &[OracleRead], path: &Path) -> Result<()> {
+    let mut out = String::from("# cycle index value\n");
+    for read in reads {
+        let cycle = read
+            .cycle
+            .map_or("-".to_string(), |cycle| cycle.to_string());
+        writeln!(out, "{cycle} {} {:08x}", read.index, read.value).unwrap();
+    }
+    fs::write(path, out)
+        .with_context(|| format!("failed to write oracle transcript to {}", path.display()))?;
+    tracing::info!(
+        "Oracle transcript with {} reads written to {}",
+        reads.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Reads the words of a transcript back in stream order, ready to be used as input.
+pub fn load_words(path: &Path) -> Result<Vec<u32>> {
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("failed to read oracle transcript {}", path.display()))?;
+    let mut words = Vec::new();
+    for (line_idx, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [_, index, value] = fields[..] else {
+            bail!(
+                "{}:{}: expected `<cycle> <index> <value>`",
+                path.display(),
+                line_idx + 1
+            );
+        };
+        let index: usize = index
+            .parse()
+            .with_context(|| format!("{}:{}: invalid index", path.display(), line_idx + 1))?;
+        if index != words.len() {
+            bail!(
+                "{}:{}: expected read {}, found {index}",
+                path.display(),
+                line_idx + 1,
+                words.len()
+            );
+        }
+        let value = u32::from_str_radix(value, 16)
+            .with_context(|| format!("{}:{}: invalid value", path.display(), line_idx + 1))?;
+        words.push(value);
+    }
+    Ok(words)
+}
+
+/// Warns when the guest did not read exactly the words it was given.
+pub fn report_consumption(provided: usize, read: usize) {
+    if read < provided {
+        tracing::warn!(
+            "Guest read {read} of {provided} input words; {} were never consumed",
+            provided - read
+        );
+    } else if read > provided {
+        tracing::warn!(
+            "Guest read {read} input words but only {provided} were provided; {} reads ran past the end",
+            read - provided
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn transcript_file(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("airbender-cli-{}-{name}.txt", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn loads_what_write_produces() {
+        let reads = [
+            OracleRead {
+                cycle: Some(10),
+                index: 0,
+                value: 0xdead_beef,
+            },
+            OracleRead {
+                cycle: None,
+                index: 1,
+                value: 7,
+            },
+        ];
+        let path = transcript_file("roundtrip", "");
+        write(&reads, &path).unwrap();
+        let words = load_words(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(words.unwrap(), [0xdead_beef, 7]);
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let path = transcript_file(
+            "comments",
+            "# cycle index value\n\n5 0 0000000a\n  \n- 1 ff\n",
+        );
+        let words = load_words(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(words.unwrap(), [10, 0xff]);
+    }
+
+    #[test]
+    fn rejects_malformed_transcripts() {
+        for (name, contents) in [
+            ("fields", "5 0\n"),
+            ("gap", "5 0 1\n6 2 2\n"),
+            ("index", "5 x 1\n"),
+            ("value", "5 0 zz\n"),
+        ] {
+            let path = transcript_file(name, contents);
+            let words = load_words(&path);
+            fs::remove_file(&path).unwrap();
+            assert!(words.is_err(), "{name}: {contents:?} was accepted");
+        }
+    }
+}
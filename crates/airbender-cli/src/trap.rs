@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::callstack::{CallEvent, CallStack};
+use crate::elf::SymbolTable;
+use crate::riscv::{self, Instruction, REGISTER_NAMES};
+use crate::sim::{Step, StepAction, StepObserver};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapKind {
+    IllegalInstruction,
+    MisalignedFetch,
+    MisalignedAccess {
+        address: u32,
+        width: u32,
+    },
+    /// The guest entered the panic handler and then stopped making progress.
+    Panic,
+    /// The guest loops without changing registers or memory.
+    TightLoop,
+    /// The guest tried to read past the end of its input.
+    InputExhausted,
+}
+
+impl fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrapKind::IllegalInstruction => write!(f, "illegal instruction"),
+            TrapKind::MisalignedFetch => write!(f, "misaligned instruction fetch"),
+            TrapKind::MisalignedAccess { address, width } => {
+                write!(f, "misaligned {width}-byte access at 0x{address:08x}")
+            }
+            TrapKind::Panic => write!(f, "panic"),
+            TrapKind::TightLoop => write!(f, "infinite loop without progress"),
+            TrapKind::InputExhausted => write!(f, "read past the end of the input"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub pc: u32,
+    /// Demangled function name and the offset of `pc` within it.
+    pub function: Option<(String, u32)>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:08x}", self.pc)?;
+        if let Some((name, offset)) = &self.function {
+            write!(f, " {name}+0x{offset:x}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Trap {
+    pub kind: TrapKind,
+    pub cycle: usize,
+    pub instruction: u32,
+    pub registers: [u32; 32],
+    /// Innermost frame first.
+    pub backtrace: Vec<Frame>,
+}
+
+/// Detects traps and panics, keeping a shadow call stack for symbolized backtraces.
+pub struct TrapDetector<'a> {
+    symbols: Option<&'a SymbolTable>,
+    call_stack: CallStack,
+    /// Whether a function entry is part of the panic machinery, cached by address.
+    panic_entries: HashMap<u32, bool>,
+    panic: Option<Trap>,
+    trap: Option<Trap>,
+    last_pc: Option<u32>,
+    /// Loop head and register file seen at the last backward jump.
+    loop_head: Option<(u32, [u32; 32])>,
+    /// Whether a store or CSR access happened since `loop_head` was recorded.
+    loop_has_side_effects: bool,
+}
+
+impl<'a> TrapDetector<'a> {
+    pub fn new(symbols: Option<&'a SymbolTable>) -> Self {
+        Self {
+            symbols,
+            call_stack: CallStack::default(),
+            panic_entries: HashMap::new(),
+            panic: None,
+            trap: None,
+            last_pc: None,
+            loop_head: None,
+            loop_has_side_effects: false,
+        }
+    }
+
+    /// Returns the detected trap, treating a finished run that went through the panic handler as a panic.
+    pub fn finish(self, reached_end: bool) -> Option<Trap> {
+        match self.trap {
+            Some(trap) => Some(trap),
+            None if reached_end => self.panic,
+            None => None,
+        }
+    }
+
+    /// Shadow call stack as (call site, return address), outermost first.
+    pub fn call_stack(&self) -> Vec<(u32, u32)> {
+        self.call_stack.calls().to_vec()
+    }
+
+    /// Continues from a shadow call stack returned by [`Self::call_stack`].
+    pub fn restore_call_stack(&mut self, calls: &[(u32, u32)]) {
+        self.call_stack = CallStack::from_calls(calls.to_vec());
+    }
+
+    /// Records a trap detected outside of this detector at `step`.
+    pub fn raise(&mut self, kind: TrapKind, step: &Step<'_>) {
+        self.trap = Some(self.capture(kind, step));
+    }
+
+    fn frame(&self, pc: u32) -> Frame {
+        let function = self
+            .symbols
+            .and_then(|symbols| symbols.lookup(pc))
+            .map(|symbol| (symbol.name.clone(), pc - symbol.address));
+        Frame { pc, function }
+    }
+
+    fn capture(&self, kind: TrapKind, step: &Step<'_>) -> Trap {
+        let mut backtrace = vec![self.frame(step.pc)];
+        for &(call_site, _) in self.call_stack.calls().iter().rev() {
+            backtrace.push(self.frame(call_site));
+        }
+        Trap {
+            kind,
+            cycle: step.cycle,
+            instruction: step.instruction,
+            registers: *step.registers,
+            backtrace,
+        }
+    }
+
+    fn is_panic_entry(&mut self, address: u32) -> bool {
+        let symbols = self.symbols;
+        *self.panic_entries.entry(address).or_insert_with(|| {
+            symbols
+                .and_then(|symbols| symbols.lookup(address))
+                .is_some_and(|symbol| {
+                    symbol.address == address
+                        && (symbol.name == "rust_begin_unwind"
+                            || symbol.name.starts_with("core::panicking::"))
+                })
+        })
+    }
+
+    fn detect(&mut self, step: &Step<'_>) -> Option<TrapKind> {
+        if !step.pc.is_multiple_of(4) {
+            return Some(TrapKind::MisalignedFetch);
+        }
+        let Some(instruction) = riscv::decode(step.instruction) else {
+            return Some(TrapKind::IllegalInstruction);
+        };
+        if let Some((address, width)) = instruction.memory_access(step.registers)
+            && !address.is_multiple_of(width)
+        {
+            return Some(TrapKind::MisalignedAccess { address, width });
+        }
+
+        if self.last_pc.is_some_and(|last_pc| step.pc <= last_pc) {
+            if self.loop_head.as_ref().is_some_and(|(head, registers)| {
+                *head == step.pc && registers == step.registers && !self.loop_has_side_effects
+            }) {
+                return Some(TrapKind::TightLoop);
+            }
+            self.loop_head = Some((step.pc, *step.registers));
+            self.loop_has_side_effects = false;
+        }
+        if matches!(
+            instruction,
+            Instruction::Store { .. } | Instruction::Csr { .. }
+        ) {
+            self.loop_has_side_effects = true;
+        }
+
+        self.track_calls(step);
+        None
+    }
+
+    fn track_calls(&mut self, step: &Step<'_>) {
+        let Some(event) = self.call_stack.event(step) else {
+            return;
+        };
+        // Captured before the call is pushed, so the innermost frame is the calling instruction.
+        if let CallEvent::Call { target } = event
+            && self.panic.is_none()
+            && self.is_panic_entry(target)
+        {
+            self.panic = Some(self.capture(TrapKind::Panic, step));
+        }
+        self.call_stack.apply(step, event);
+    }
+}
+
+impl StepObserver for TrapDetector<'_> {
+    fn on_step(&mut self, step: &Step<'_>) -> StepAction {
+        let action = match self.detect(step) {
+            Some(kind) => {
+                self.trap = Some(self.capture(kind, step));
+                StepAction::Stop
+            }
+            None => StepAction::Continue,
+        };
+        self.last_pc = Some(step.pc);
+        action
+    }
+}
+
+pub fn report_trap(trap: &Trap) {
+    tracing::error!(
+        "Guest trapped: {} at cycle {}, pc={}, instruction=0x{:08x}",
+        trap.kind,
+        trap.cycle,
+        trap.backtrace[0],
+        trap.instruction
+    );
+    tracing::error!("Backtrace:");
+    for (idx, frame) in trap.backtrace.iter().enumerate() {
+        tracing::error!("  #{idx} {frame}");
+    }
+    tracing::error!("Registers:");
+    for (names, values) in REGISTER_NAMES.chunks(4).zip(trap.registers.chunks(4)) {
+        let line: Vec<String> = names
+            .iter()
+            .zip(values)
+            .map(|(name, value)| format!("{name:>4}=0x{value:08x}"))
+            .collect();
+        tracing::error!("  {}", line.join(" "));
+    }
+}
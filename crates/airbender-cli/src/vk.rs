@@ -1,23 +1,30 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use execution_utils::setups;
 use execution_utils::unified_circuit::verify_proof_in_unified_layer;
 use execution_utils::unrolled::{
-    compute_setup_for_machine_configuration, get_unrolled_circuits_artifacts_for_machine_type,
-    verify_unrolled_layer_proof, UnrolledProgramProof, UnrolledProgramSetup,
+    UnrolledProgramProof, UnrolledProgramSetup, compute_setup_for_machine_configuration,
+    get_unrolled_circuits_artifacts_for_machine_type, verify_unrolled_layer_proof,
 };
 use execution_utils::unrolled_gpu::UnrolledProverLevel;
-use execution_utils::setups;
 use risc_v_simulator::cycle::{
-    IMStandardIsaConfigWithUnsignedMulDiv, IWithoutByteAccessIsaConfigWithDelegation,
+    IMStandardIsaConfig, IMStandardIsaConfigWithUnsignedMulDiv,
+    IWithoutByteAccessIsaConfigWithDelegation, MachineConfig,
 };
 use sha3::Digest;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+
+use crate::cli::Machine;
+use crate::program::Program;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct UnifiedVkFile {
     pub app_bin_hash: [u8; 32],
     pub unified_setup: UnrolledProgramSetup,
     pub unified_layouts: setups::CompiledCircuitsSet,
+    /// Machine configuration of the base layer. Last, so that older files still decode as
+    /// `LegacyUnifiedVkFile`.
+    pub machine: Machine,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -25,30 +32,72 @@ pub struct UnrolledVkFile {
     pub app_bin_hash: [u8; 32],
     pub setup: UnrolledProgramSetup,
     pub compiled_layouts: setups::CompiledCircuitsSet,
+    /// Machine configuration of the base layer. Last, so that older files still decode as
+    /// `LegacyUnrolledVkFile`.
+    pub machine: Machine,
+}
+
+/// `UnifiedVkFile` as written before VK files recorded their machine.
+#[derive(serde::Deserialize)]
+struct LegacyUnifiedVkFile {
+    app_bin_hash: [u8; 32],
+    unified_setup: UnrolledProgramSetup,
+    unified_layouts: setups::CompiledCircuitsSet,
+}
+
+/// `UnrolledVkFile` as written before VK files recorded their machine.
+#[derive(serde::Deserialize)]
+struct LegacyUnrolledVkFile {
+    app_bin_hash: [u8; 32],
+    setup: UnrolledProgramSetup,
+    compiled_layouts: setups::CompiledCircuitsSet,
 }
 
-pub fn generate_vk(app_bin: &Path, output: &Path, level: UnrolledProverLevel) -> Result<()> {
+/// Machine of VK files without one: before `--machine` existed, base layer VKs were always
+/// generated with `IMStandardIsaConfigWithUnsignedMulDiv`.
+const LEGACY_VK_MACHINE: Machine = Machine::UnsignedMulDiv;
+
+pub fn generate_vk(
+    program: &Program,
+    output: &Path,
+    level: UnrolledProverLevel,
+    machine: Machine,
+) -> Result<()> {
+    // Recursion layers only accept base layer proofs from the GPU prover's configuration.
+    if level != UnrolledProverLevel::Base && machine != Machine::UnsignedMulDiv {
+        bail!(
+            "recursion levels require {}, got {machine}; use --level base",
+            Machine::UnsignedMulDiv
+        );
+    }
     match level {
-        UnrolledProverLevel::RecursionUnified => generate_unified_vk(app_bin, output),
+        UnrolledProverLevel::RecursionUnified => generate_unified_vk(program, output, machine),
         UnrolledProverLevel::Base | UnrolledProverLevel::RecursionUnrolled => {
-            generate_unrolled_vk(app_bin, output, level)
+            match machine {
+                Machine::Standard => {
+                    generate_unrolled_vk::<IMStandardIsaConfig>(program, output, level, machine)
+                }
+                Machine::UnsignedMulDiv => generate_unrolled_vk::<
+                    IMStandardIsaConfigWithUnsignedMulDiv,
+                >(program, output, level, machine),
+            }
         }
     }
 }
 
-fn generate_unified_vk(app_bin: &Path, output: &Path) -> Result<()> {
-    let app_bin_bytes =
-        fs::read(app_bin).with_context(|| format!("failed to read {}", app_bin.display()))?;
-    let app_bin_hash: [u8; 32] = sha3::Keccak256::digest(&app_bin_bytes).into();
+fn generate_unified_vk(program: &Program, output: &Path, machine: Machine) -> Result<()> {
+    let app_bin_hash: [u8; 32] = sha3::Keccak256::digest(&program.bin).into();
 
     let (binary, binary_u32) =
         setups::pad_binary(execution_utils::unrolled_gpu::RECURSION_UNIFIED_BIN.to_vec());
-    let (text, _) = setups::pad_binary(execution_utils::unrolled_gpu::RECURSION_UNIFIED_TXT.to_vec());
+    let (text, _) =
+        setups::pad_binary(execution_utils::unrolled_gpu::RECURSION_UNIFIED_TXT.to_vec());
 
     tracing::info!("Computing unified recursion VKs");
-    let unified_setup = execution_utils::unified_circuit::compute_unified_setup_for_machine_configuration::<
-        IWithoutByteAccessIsaConfigWithDelegation,
-    >(&binary, &text);
+    let unified_setup =
+        execution_utils::unified_circuit::compute_unified_setup_for_machine_configuration::<
+            IWithoutByteAccessIsaConfigWithDelegation,
+        >(&binary, &text);
     let unified_layouts = execution_utils::setups::get_unified_circuit_artifact_for_machine_type::<
         IWithoutByteAccessIsaConfigWithDelegation,
     >(&binary_u32);
@@ -57,6 +106,7 @@ fn generate_unified_vk(app_bin: &Path, output: &Path) -> Result<()> {
         app_bin_hash,
         unified_setup,
         unified_layouts,
+        machine,
     };
     let encoded = bincode::serde::encode_to_vec(&vk_file, bincode::config::standard())?;
     fs::write(output, encoded)
@@ -65,23 +115,18 @@ fn generate_unified_vk(app_bin: &Path, output: &Path) -> Result<()> {
     Ok(())
 }
 
-fn generate_unrolled_vk(
-    app_bin: &Path,
+fn generate_unrolled_vk<C: MachineConfig>(
+    program: &Program,
     output: &Path,
     level: UnrolledProverLevel,
+    machine: Machine,
 ) -> Result<()> {
-    let base_path = strip_bin_suffix(app_bin)?;
-    let app_bin_path = PathBuf::from(format!("{base_path}.bin"));
-    let app_text_path = PathBuf::from(format!("{base_path}.text"));
-
-    let app_bin_bytes =
-        fs::read(&app_bin_path).with_context(|| format!("failed to read {}", app_bin_path.display()))?;
-    let app_bin_hash: [u8; 32] = sha3::Keccak256::digest(&app_bin_bytes).into();
+    let app_bin_hash: [u8; 32] = sha3::Keccak256::digest(&program.bin).into();
 
     let (binary, binary_u32, text) = match level {
         UnrolledProverLevel::Base => {
-            let (binary, binary_u32) = setups::read_and_pad_binary(&app_bin_path);
-            let (text, _) = setups::read_and_pad_binary(&app_text_path);
+            let (binary, binary_u32) = setups::pad_binary(program.bin.clone());
+            let (text, _) = setups::pad_binary(program.text()?.to_vec());
             (binary, binary_u32, text)
         }
         UnrolledProverLevel::RecursionUnrolled => {
@@ -98,12 +143,9 @@ fn generate_unrolled_vk(
 
     let (setup, compiled_layouts) = match level {
         UnrolledProverLevel::Base => {
-            let setup = compute_setup_for_machine_configuration::<
-                IMStandardIsaConfigWithUnsignedMulDiv,
-            >(&binary, &text);
-            let compiled_layouts = get_unrolled_circuits_artifacts_for_machine_type::<
-                IMStandardIsaConfigWithUnsignedMulDiv,
-            >(&binary_u32);
+            let setup = compute_setup_for_machine_configuration::<C>(&binary, &text);
+            let compiled_layouts =
+                get_unrolled_circuits_artifacts_for_machine_type::<C>(&binary_u32);
             (setup, compiled_layouts)
         }
         UnrolledProverLevel::RecursionUnrolled => {
@@ -124,6 +166,7 @@ fn generate_unrolled_vk(
         app_bin_hash,
         setup,
         compiled_layouts,
+        machine,
     };
     let encoded = bincode::serde::encode_to_vec(&vk_file, bincode::config::standard())?;
     fs::write(output, encoded)
@@ -136,14 +179,20 @@ pub fn verify_proof(
     proof_path: &Path,
     vk_path: &Path,
     level: UnrolledProverLevel,
+    machine: Option<Machine>,
 ) -> Result<()> {
-    let proof = read_bincode::<UnrolledProgramProof>(proof_path)
-        .context("failed to decode proof")?;
+    let proof =
+        read_bincode::<UnrolledProgramProof>(proof_path).context("failed to decode proof")?;
     tracing::info!("Verifying proof");
     match level {
         UnrolledProverLevel::RecursionUnified => {
-            let vk_file =
-                read_bincode::<UnifiedVkFile>(vk_path).context("failed to decode VK file")?;
+            let vk_file = read_vk_file(vk_path, |legacy: LegacyUnifiedVkFile| UnifiedVkFile {
+                app_bin_hash: legacy.app_bin_hash,
+                unified_setup: legacy.unified_setup,
+                unified_layouts: legacy.unified_layouts,
+                machine: LEGACY_VK_MACHINE,
+            })?;
+            check_vk_machine(vk_file.machine, machine, level)?;
             let result = verify_proof_in_unified_layer(
                 &proof,
                 &vk_file.unified_setup,
@@ -154,8 +203,13 @@ pub fn verify_proof(
             tracing::info!("Proof verified successfully, output={result:?}");
         }
         UnrolledProverLevel::Base | UnrolledProverLevel::RecursionUnrolled => {
-            let vk_file =
-                read_bincode::<UnrolledVkFile>(vk_path).context("failed to decode VK file")?;
+            let vk_file = read_vk_file(vk_path, |legacy: LegacyUnrolledVkFile| UnrolledVkFile {
+                app_bin_hash: legacy.app_bin_hash,
+                setup: legacy.setup,
+                compiled_layouts: legacy.compiled_layouts,
+                machine: LEGACY_VK_MACHINE,
+            })?;
+            check_vk_machine(vk_file.machine, machine, level)?;
             let is_base_layer = level == UnrolledProverLevel::Base;
             let result = verify_unrolled_layer_proof(
                 &proof,
@@ -170,15 +224,52 @@ pub fn verify_proof(
     Ok(())
 }
 
-fn strip_bin_suffix(path: &Path) -> Result<String> {
-    let path_str = path
-        .to_str()
-        .ok_or_else(|| anyhow::anyhow!("app path is not valid UTF-8"))?;
-    if let Some(stripped) = path_str.strip_suffix(".bin") {
-        Ok(stripped.to_string())
-    } else {
-        Ok(path_str.to_string())
+/// Checks the VK's machine against `--machine`, if given, and against what `level` accepts.
+fn check_vk_machine(
+    vk_machine: Machine,
+    expected: Option<Machine>,
+    level: UnrolledProverLevel,
+) -> Result<()> {
+    if let Some(expected) = expected
+        && expected != vk_machine
+    {
+        bail!("VK was generated for {vk_machine}, but --machine is {expected}");
     }
+    if level != UnrolledProverLevel::Base && vk_machine != Machine::UnsignedMulDiv {
+        bail!(
+            "VK was generated for {vk_machine}, but recursion levels require {}",
+            Machine::UnsignedMulDiv
+        );
+    }
+    tracing::info!("VK machine configuration: {vk_machine}");
+    Ok(())
+}
+
+/// Decodes a VK file, falling back to its layout from before the machine was recorded.
+fn read_vk_file<T, L>(path: &Path, upgrade: impl FnOnce(L) -> T) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+    L: serde::de::DeserializeOwned,
+{
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    match bincode::serde::decode_from_slice::<T, _>(&bytes, bincode::config::standard()) {
+        Ok((decoded, read_len)) if read_len == bytes.len() => return Ok(decoded),
+        _ => {}
+    }
+    let (legacy, read_len) =
+        bincode::serde::decode_from_slice::<L, _>(&bytes, bincode::config::standard())
+            .context("failed to decode VK file")?;
+    if read_len != bytes.len() {
+        bail!(
+            "failed to decode VK file: {} trailing bytes",
+            bytes.len() - read_len
+        );
+    }
+    tracing::warn!(
+        "{} does not record its machine configuration; assuming {LEGACY_VK_MACHINE}",
+        path.display()
+    );
+    Ok(upgrade(legacy))
 }
 
 fn read_bincode<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
@@ -194,3 +285,86 @@ fn read_bincode<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
     }
     Ok(decoded)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    // Setups and layouts cannot be built without running the setup, so these stand-ins keep the
+    // shape of the VK files: the same leading fields, with `machine` appended in the new layout.
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct VkFile {
+        app_bin_hash: [u8; 32],
+        setup: Vec<u32>,
+        machine: Machine,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct LegacyVkFile {
+        app_bin_hash: [u8; 32],
+        setup: Vec<u32>,
+    }
+
+    fn read(name: &str, file: &impl Serialize) -> Result<VkFile> {
+        let path =
+            std::env::temp_dir().join(format!("airbender-cli-{}-{name}.vk", std::process::id()));
+        let encoded = bincode::serde::encode_to_vec(file, bincode::config::standard()).unwrap();
+        fs::write(&path, encoded).unwrap();
+        let decoded = read_vk_file(&path, |legacy: LegacyVkFile| VkFile {
+            app_bin_hash: legacy.app_bin_hash,
+            setup: legacy.setup,
+            machine: LEGACY_VK_MACHINE,
+        });
+        fs::remove_file(&path).unwrap();
+        decoded
+    }
+
+    #[test]
+    fn baseline_vk_files_verify_at_every_level() {
+        let legacy = LegacyVkFile {
+            app_bin_hash: [7; 32],
+            setup: vec![1, 2, 3],
+        };
+        let vk_file = read("legacy", &legacy).unwrap();
+        assert_eq!(vk_file.app_bin_hash, [7; 32]);
+        assert_eq!(vk_file.setup, [1, 2, 3]);
+        assert_eq!(vk_file.machine, Machine::UnsignedMulDiv);
+        for level in [
+            UnrolledProverLevel::Base,
+            UnrolledProverLevel::RecursionUnrolled,
+            UnrolledProverLevel::RecursionUnified,
+        ] {
+            check_vk_machine(vk_file.machine, None, level).unwrap();
+        }
+        check_vk_machine(
+            vk_file.machine,
+            Some(Machine::UnsignedMulDiv),
+            UnrolledProverLevel::RecursionUnified,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn reads_the_recorded_machine() {
+        let new = VkFile {
+            app_bin_hash: [7; 32],
+            setup: vec![1, 2, 3],
+            machine: Machine::Standard,
+        };
+        let vk_file = read("new", &new).unwrap();
+        assert_eq!(vk_file, new);
+        check_vk_machine(vk_file.machine, None, UnrolledProverLevel::Base).unwrap();
+        assert!(
+            check_vk_machine(vk_file.machine, None, UnrolledProverLevel::RecursionUnified).is_err()
+        );
+        assert!(
+            check_vk_machine(
+                vk_file.machine,
+                Some(Machine::UnsignedMulDiv),
+                UnrolledProverLevel::Base
+            )
+            .is_err()
+        );
+    }
+}
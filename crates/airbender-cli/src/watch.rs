@@ -0,0 +1,190 @@
+//! Memory watchpoints: logs guest loads and stores that touch watched address ranges.
+
+use crate::cli::parse_address;
+use crate::elf::SymbolTable;
+use crate::riscv::{self, Instruction};
+use crate::sim::{Step, StepAction, StepObserver};
+use crate::trap::Frame;
+
+/// A watched address range, parsed from `addr[:len][:rw]`.
+#[derive(Debug, Clone, Copy)]
+pub struct Watchpoint {
+    pub address: u32,
+    /// Length in bytes; defaults to one word.
+    pub len: u32,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    fn overlaps(&self, address: u32, width: u32) -> bool {
+        let start = u64::from(self.address);
+        let address = u64::from(address);
+        address < start + u64::from(self.len) && start < address + u64::from(width)
+    }
+}
+
+pub fn parse_watchpoint(value: &str) -> Result<Watchpoint, String> {
+    let mut parts = value.split(':');
+    let address = parse_address(parts.next().unwrap_or_default())?;
+    let mut watchpoint = Watchpoint {
+        address,
+        len: 4,
+        read: true,
+        write: true,
+    };
+    for part in parts {
+        match part {
+            "r" => (watchpoint.read, watchpoint.write) = (true, false),
+            "w" => (watchpoint.read, watchpoint.write) = (false, true),
+            "rw" | "wr" => (watchpoint.read, watchpoint.write) = (true, true),
+            len => {
+                watchpoint.len = parse_address(len)?;
+                if watchpoint.len == 0 {
+                    return Err(format!("watchpoint `{value}` has zero length"));
+                }
+            }
+        }
+    }
+    Ok(watchpoint)
+}
+
+/// Step observer that logs every access matching a watchpoint.
+pub struct Watcher<'a> {
+    watchpoints: Vec<Watchpoint>,
+    symbols: Option<&'a SymbolTable>,
+    /// Ends the run before the first matching access executes.
+    stop_on_hit: bool,
+    hits: usize,
+}
+
+impl<'a> Watcher<'a> {
+    pub fn new(
+        watchpoints: Vec<Watchpoint>,
+        symbols: Option<&'a SymbolTable>,
+        stop_on_hit: bool,
+    ) -> Self {
+        Self {
+            watchpoints,
+            symbols,
+            stop_on_hit,
+            hits: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    pub fn report(&self) {
+        tracing::info!("{} watchpoint hits", self.hits);
+    }
+
+    fn frame(&self, pc: u32) -> Frame {
+        let function = self
+            .symbols
+            .and_then(|symbols| symbols.lookup(pc))
+            .map(|symbol| (symbol.name.clone(), pc - symbol.address));
+        Frame { pc, function }
+    }
+}
+
+impl StepObserver for Watcher<'_> {
+    fn on_step(&mut self, step: &Step<'_>) -> StepAction {
+        let Some(instruction) = riscv::decode(step.instruction) else {
+            return StepAction::Continue;
+        };
+        let Some((address, width)) = instruction.memory_access(step.registers) else {
+            return StepAction::Continue;
+        };
+        let store_value = match instruction {
+            Instruction::Store { rs2, .. } => Some(step.registers[rs2 as usize]),
+            _ => None,
+        };
+        let is_store = store_value.is_some();
+        let hit = self.watchpoints.iter().any(|watchpoint| {
+            let kind_matches = if is_store {
+                watchpoint.write
+            } else {
+                watchpoint.read
+            };
+            kind_matches && watchpoint.overlaps(address, width)
+        });
+        if !hit {
+            return StepAction::Continue;
+        }
+
+        self.hits += 1;
+        let old = read_bytes(step.memory, address, width);
+        let digits = width as usize * 2;
+        let frame = self.frame(step.pc);
+        match store_value {
+            Some(value) => {
+                let new = if width == 4 {
+                    value
+                } else {
+                    value & ((1 << (width * 8)) - 1)
+                };
+                tracing::info!(
+                    "cycle {}: store {width} bytes at 0x{address:08x}: 0x{old:0digits$x} -> 0x{new:0digits$x} at {frame}",
+                    step.cycle
+                );
+            }
+            None => tracing::info!(
+                "cycle {}: load {width} bytes at 0x{address:08x}: 0x{old:0digits$x} at {frame}",
+                step.cycle
+            ),
+        }
+
+        if self.stop_on_hit {
+            tracing::info!("Stopping at the first watchpoint hit, before the access executes");
+            return StepAction::Stop;
+        }
+        StepAction::Continue
+    }
+}
+
+/// Reads `width` bytes at `address` from word-addressed memory, little-endian.
+fn read_bytes(memory: &[u32], address: u32, width: u32) -> u32 {
+    (0..width).fold(0, |value, offset| {
+        let byte_address = address.wrapping_add(offset);
+        let word = memory
+            .get((byte_address / 4) as usize)
+            .copied()
+            .unwrap_or(0);
+        let byte = (word >> ((byte_address % 4) * 8)) & 0xff;
+        value | (byte << (offset * 8))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_to_one_word_read_and_written() {
+        let watchpoint = parse_watchpoint("0x1000").unwrap();
+        assert_eq!(watchpoint.address, 0x1000);
+        assert_eq!(watchpoint.len, 4);
+        assert!(watchpoint.read && watchpoint.write);
+    }
+
+    #[test]
+    fn parses_length_and_access() {
+        let watchpoint = parse_watchpoint("0x1000:16:w").unwrap();
+        assert_eq!(watchpoint.len, 16);
+        assert!(!watchpoint.read && watchpoint.write);
+
+        let watchpoint = parse_watchpoint("4096:r").unwrap();
+        assert_eq!(watchpoint.address, 4096);
+        assert_eq!(watchpoint.len, 4);
+        assert!(watchpoint.read && !watchpoint.write);
+    }
+
+    #[test]
+    fn rejects_invalid_watchpoints() {
+        assert!(parse_watchpoint("0x1000:0").is_err());
+        assert!(parse_watchpoint("0x1000:x").is_err());
+        assert!(parse_watchpoint("heap").is_err());
+    }
+}
@@ -0,0 +1,10 @@
+//! Helpers shared by the XML report writers (Cobertura coverage, JUnit test results).
+
+/// Escapes `value` for use in XML text and double-quoted attributes.
+pub fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}